@@ -0,0 +1,140 @@
+//! Build script that compiles `capdata.yaml` into the crate, for `TermCapSet::builtin`
+//!
+//! The data is turned into Rust code that constructs each `LabelledTermCap` directly, so that the
+//! built-in capabilities don't need a YAML parser at runtime. Field names are taken from the keys
+//! in the file, so only the types of nested structs and enums need to be listed here -- anything
+//! that's missing or out of date shows up as a compile error in the generated code.
+//!
+//! Keys and variants may be spelled any way that the serde aliases in `capinfo.rs` allow. The
+//! `test_builtin` test checks that the result matches what's deserialized at runtime.
+
+use serde_yaml::Value;
+use std::path::Path;
+use std::{env, fs};
+
+/// The type of each field that isn't a `bool` or `Option<usize>`, as `(TYPE, FIELD, FIELD_TYPE)`
+const FIELD_TYPES: &[(&str, &str, &str)] = &[
+    ("TerminalName", "compact", "String"),
+    ("TerminalName", "pretty", "String"),
+    ("TerminalName", "term", "String"),
+    ("TermCap", "style", "StyleCap"),
+    ("TermCap", "cursor", "CursorCap"),
+    ("TermCap", "scroll", "ScrollCap"),
+    ("TermCap", "erase", "EraseCap"),
+    ("TermCap", "edit", "EditCap"),
+    ("TermCap", "mode", "ModeCap"),
+    ("TermCap", "window", "WindowCap"),
+    ("TermCap", "clipboard", "ClipboardCap"),
+    ("TermCap", "palette", "PaletteCap"),
+    ("TermCap", "keyboard", "KeyboardCap"),
+    ("StyleCap", "set_color", "ColorCap"),
+    ("StyleCap", "set_underline", "UnderlineCap"),
+    ("CursorCap", "set_style", "CursorStyleCap"),
+];
+
+/// The variants of each enum, as `(TYPE, VARIANT, CONTENTS)`
+const ENUM_VARIANTS: &[(&str, &str, Option<&str>)] = &[
+    ("ColorCap", "None", None),
+    ("ColorCap", "Fixed4Bit", None),
+    ("ColorCap", "Fixed8Bit", None),
+    ("ColorCap", "Rgb", Some("RgbCapSet")),
+    ("UnderlineCap", "None", None),
+    ("UnderlineCap", "Basic", None),
+    ("UnderlineCap", "Fancy", Some("FancyUnderlineCap")),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=capdata.yaml");
+
+    let content = fs::read_to_string("capdata.yaml").expect("failed to read capdata.yaml");
+    let terminals: Vec<Value> =
+        serde_yaml::from_str(&content).expect("failed to parse capdata.yaml");
+
+    let mut code = String::from("vec![\n");
+    for terminal in terminals {
+        // Everything other than the name is flattened into the `TermCap`
+        let Value::Mapping(mut caps) = terminal else {
+            panic!("expected a mapping for each terminal in capdata.yaml");
+        };
+        let name = caps.remove("name").expect("missing name in capdata.yaml");
+        code.push_str(&format!(
+            "    LabelledTermCap {{ name: {}, caps: {} }},\n",
+            expr("TerminalName", &name),
+            expr("TermCap", &Value::Mapping(caps)),
+        ));
+    }
+    code.push(']');
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("builtin_caps.rs"), code).unwrap();
+}
+
+/// Returns the Rust expression for a value of type `ty`
+fn expr(ty: &str, value: &Value) -> String {
+    match value {
+        Value::Bool(b) => b.to_string(),
+        Value::Null => "None".to_owned(),
+        Value::Number(n) => format!("Some({n})"),
+        Value::String(s) if ty == "String" => format!("{s:?}.to_owned()"),
+        Value::String(s) => variant(ty, s, None),
+        Value::Mapping(map) if ENUM_VARIANTS.iter().any(|(t, ..)| *t == ty) => {
+            let mut entries = map.iter();
+            match (entries.next(), entries.next()) {
+                (Some((Value::String(name), contents)), None) => variant(ty, name, Some(contents)),
+                _ => panic!("expected a single variant of {ty} in capdata.yaml"),
+            }
+        }
+        Value::Mapping(map) => {
+            let fields: Vec<_> = map
+                .iter()
+                .map(|(key, value)| {
+                    let key = key.as_str().expect("expected string keys in capdata.yaml");
+                    let field = field_name(key);
+                    let field_ty = FIELD_TYPES
+                        .iter()
+                        .find(|(t, f, _)| *t == ty && *f == field)
+                        .map_or("", |(_, _, field_ty)| field_ty);
+                    format!("{field}: {}", expr(field_ty, value))
+                })
+                .collect();
+            format!("{ty} {{ {} }}", fields.join(", "))
+        }
+        _ => panic!("unexpected value for {ty} in capdata.yaml: {value:?}"),
+    }
+}
+
+/// Returns the name of the field for a key, which may also be in one of the forms that the serde
+/// aliases allow: `kebab-case`, `camelCase`, or `Capitalized`
+fn field_name(key: &str) -> String {
+    let mut field = String::new();
+    for (i, c) in key.chars().enumerate() {
+        match c {
+            '-' => field.push('_'),
+            _ if c.is_ascii_uppercase() => {
+                if i > 0 {
+                    field.push('_');
+                }
+                field.push(c.to_ascii_lowercase());
+            }
+            _ => field.push(c),
+        }
+    }
+    field
+}
+
+/// Returns the Rust expression for the variant of the enum `ty` with the given name, ignoring case
+/// and hyphens
+fn variant(ty: &str, name: &str, contents: Option<&Value>) -> String {
+    let name = name.replace('-', "");
+    let found = ENUM_VARIANTS
+        .iter()
+        .find(|(t, v, _)| *t == ty && v.eq_ignore_ascii_case(&name));
+
+    match (found, contents) {
+        (Some((_, v, None)), None) => format!("{ty}::{v}"),
+        (Some((_, v, Some(inner))), Some(contents)) => {
+            format!("{ty}::{v}({})", expr(inner, contents))
+        }
+        _ => panic!("unexpected variant of {ty} in capdata.yaml: {name}"),
+    }
+}
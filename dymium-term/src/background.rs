@@ -0,0 +1,143 @@
+//! Detecting whether the terminal has a light or dark background
+
+use crate::Color;
+
+#[cfg(unix)]
+use crate::{capinfo::TermCap, tty, PaletteEntry, PaletteReply};
+#[cfg(unix)]
+use std::{io, os::unix::io::AsRawFd, time::Duration, time::Instant};
+
+/// Whether a color is light or dark
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Brightness {
+    #[allow(missing_docs)]
+    Light,
+    #[allow(missing_docs)]
+    Dark,
+}
+
+/// Information about the terminal's background, from [`Background::detect`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Background {
+    /// Whether the background is light or dark
+    pub brightness: Brightness,
+    /// The background color, if it was reported by the terminal
+    ///
+    /// This is `None` if the brightness was determined from `$COLORFGBG` instead.
+    pub color: Option<Color>,
+}
+
+impl Brightness {
+    /// Classifies the color as light or dark, based on its relative luminance
+    ///
+    /// Colors with a perceived lightness of at least 50% are considered light -- which
+    /// corresponds to a relative luminance of about 0.18, *not* 0.5.
+    ///
+    /// [`Color::Fixed`] values are converted to RGB assuming Xterm's default palette, so the
+    /// result is only approximate for them.
+    pub fn of_color(color: Color) -> Self {
+        let (r, g, b) = color.approx_rgb();
+        let linear = |c: u8| {
+            let c = c as f64 / 255.0;
+            match c <= 0.04045 {
+                true => c / 12.92,
+                false => ((c + 0.055) / 1.055).powf(2.4),
+            }
+        };
+
+        let luminance = 0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b);
+        match luminance >= 0.1842 {
+            true => Brightness::Light,
+            false => Brightness::Dark,
+        }
+    }
+
+    /// Determines the background brightness from a value of the `$COLORFGBG` environment variable
+    ///
+    /// The value has the form `<FG>;<BG>` (or `<FG>;<XPM>;<BG>`, as set by rxvt), where each is an
+    /// index into the 16-color palette. Returns `None` if the background is unknown (e.g.,
+    /// `default`) or the value is malformed.
+    pub fn from_colorfgbg(value: &str) -> Option<Self> {
+        let (_, bg) = value.rsplit_once(';')?;
+        match bg.parse::<u8>().ok()? {
+            0..=6 | 8 => Some(Brightness::Dark),
+            7 | 9..=15 => Some(Brightness::Light),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Background {
+    /// Detects the terminal's background by querying its color, falling back to `$COLORFGBG`
+    ///
+    /// The query is only sent if the terminal supports it (see [`PaletteCap::query_dynamic`]).
+    /// Alongside it, we send a request for the primary device attributes, which essentially all
+    /// terminals reply to; this way, we can stop waiting early if the terminal ignored the query.
+    /// Otherwise, we wait up to `timeout` for replies.
+    ///
+    /// While waiting, canonical mode and echoing are temporarily disabled for the tty, if they
+    /// weren't already. Any other input received in the meantime (e.g., key presses) is
+    /// discarded, so this is best done on startup, before handling user input.
+    ///
+    /// Returns `Ok(None)` if the background couldn't be determined either way.
+    ///
+    /// [`PaletteCap::query_dynamic`]: crate::capinfo::PaletteCap::query_dynamic
+    pub fn detect<T: io::Write + AsRawFd>(
+        tty: &mut T,
+        caps: &TermCap,
+        timeout: Duration,
+    ) -> io::Result<Option<Self>> {
+        let from_env = || {
+            let value = std::env::var("COLORFGBG").ok()?;
+            let brightness = Brightness::from_colorfgbg(&value)?;
+            Some(Background { brightness, color: None })
+        };
+
+        if !caps.palette.query_dynamic {
+            return Ok(from_env());
+        }
+
+        let fd = tty.as_raw_fd();
+        let _guard = tty::NoEchoGuard::new(fd)?;
+
+        PaletteEntry::Background.write_query(tty)?;
+        tty.write_all(b"\x1b[c")?;
+        tty.flush()?;
+
+        let mut buf = Vec::new();
+        let deadline = Instant::now() + timeout;
+        tty::read_until(fd, &mut buf, deadline, |b| tty::find_da1_reply(b).is_some())?;
+
+        let reply = tty::find(&buf, b"\x1b]11;").and_then(|start| {
+            let body = &buf[start + 2..];
+            let end = body.iter().position(|&b| b == b'\x07' || b == b'\x1b')?;
+            PaletteReply::parse_osc_body(&body[..end])
+        });
+
+        match reply {
+            Some(PaletteReply { color, .. }) => {
+                let brightness = Brightness::of_color(color);
+                Ok(Some(Background { brightness, color: Some(color) }))
+            }
+            None => Ok(from_env()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_brightness() {
+    let of_rgb = |r, g, b| Brightness::of_color(Color::Rgb(r, g, b));
+
+    assert_eq!(of_rgb(0, 0, 0), Brightness::Dark);
+    assert_eq!(of_rgb(0x28, 0x2c, 0x34), Brightness::Dark);
+    assert_eq!(of_rgb(0xfd, 0xf6, 0xe3), Brightness::Light);
+    assert_eq!(of_rgb(0x80, 0x80, 0x80), Brightness::Light);
+
+    let from_env = Brightness::from_colorfgbg;
+    assert_eq!(from_env("15;0"), Some(Brightness::Dark));
+    assert_eq!(from_env("0;default;15"), Some(Brightness::Light));
+    assert_eq!(from_env("7;default"), None);
+    assert_eq!(from_env(""), None);
+}
@@ -8,7 +8,7 @@ use std::process::exit;
 
 use dymium_term::capinfo::{self, TerminalName};
 
-static USAGE: &str = concat!("Usage: verify-caps ( -h | <FILE> )",);
+static USAGE: &str = "Usage: verify-caps ( -h | <FILE> )";
 
 struct Args {
     file: PathBuf,
@@ -3,9 +3,12 @@
 use serde::de::{Deserializer, Error};
 use serde::Deserialize;
 use std::collections::BTreeMap;
+#[cfg(feature = "yaml")]
+use std::fs;
+use std::io;
+#[cfg(feature = "yaml")]
 use std::path::Path;
 use std::sync::Arc;
-use std::{fs, io};
 use thiserror::Error;
 
 /// Capabilities for a set of terminal emulators or similar programs
@@ -31,6 +34,119 @@ pub struct TermCapGroup {
     members: BTreeMap<String, Arc<LabelledTermCap>>,
 }
 
+/// The terminal identified by [`GroupedTermCaps::detect`], with how it was identified
+#[derive(Debug, Clone)]
+pub struct DetectedTermCap {
+    /// The capabilities to use for the terminal
+    ///
+    /// If only `$TERM` was recognized, this is the minimum among the terminals that use it.
+    pub caps: TermCap,
+    /// The specific terminal, if it could be narrowed down to one
+    pub terminal: Option<Arc<LabelledTermCap>>,
+    /// The environment variable that identified the terminal
+    pub signal: DetectionSignal,
+    /// How likely the identification is to be right
+    pub confidence: Confidence,
+    /// The version of the terminal, from `$TERM_PROGRAM_VERSION` or `$VTE_VERSION`, if known
+    pub version: Option<String>,
+    /// The terminal multiplexer that the program is running inside, if any
+    ///
+    /// Multiplexers pass through only some of the outer terminal's capabilities, and the
+    /// environment they were started from may not describe the terminal that's attached now -- so
+    /// the confidence is always [`Confidence::Low`] inside one.
+    pub multiplexer: Option<Multiplexer>,
+}
+
+/// The environment variable that [`GroupedTermCaps::detect`] identified the terminal from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DetectionSignal {
+    /// `$KITTY_WINDOW_ID` is set, which only Kitty does
+    KittyWindowId,
+    /// `$TERM_PROGRAM` names a known terminal
+    TermProgram,
+    /// `$COLORTERM` names a known terminal, as some older terminals set it to
+    ColorTerm,
+    /// `$VTE_VERSION` is set, so the terminal is based on VTE
+    VteVersion,
+    /// Only `$TERM` was recognized, possibly after normalizing it as in
+    /// [`GroupedTermCaps::lookup`]
+    Term,
+}
+
+/// How confident [`GroupedTermCaps::detect`] is in the terminal it identified
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// The terminal is only known to use the value of `$TERM`, or the environment might be from
+    /// another terminal
+    Low,
+    /// A single terminal was identified from one signal: either a terminal-specific variable that
+    /// `$TERM` doesn't corroborate, or `$TERM` alone, when it exactly matches only one terminal
+    Medium,
+    /// A terminal-specific variable identified the terminal, and it uses the value of `$TERM`
+    High,
+}
+
+/// A terminal multiplexer, from [`DetectedTermCap::multiplexer`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Multiplexer {
+    /// tmux, detected by `$TMUX` or `$TERM_PROGRAM`
+    Tmux,
+    /// GNU Screen, detected by `$STY`
+    Screen,
+}
+
+/// The result of [`GroupedTermCaps::lookup`]
+#[derive(Debug, Clone)]
+pub struct TermLookup {
+    /// The capabilities for the value of `$TERM`
+    pub caps: TermCap,
+    /// How the value of `$TERM` was matched
+    pub matched: TermMatch,
+}
+
+/// How [`GroupedTermCaps::lookup`] matched a value of `$TERM`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TermMatch {
+    /// The value is in the data as-is
+    Exact,
+    /// The value matched `term` after removing known prefixes and suffixes, or adding `-256color`
+    #[allow(missing_docs)]
+    Normalized { term: String },
+    /// The value matched `term`, a more general member of the terminal's family
+    #[allow(missing_docs)]
+    Family { term: String },
+    /// Nothing matched, so [`TermCap::baseline`] was used
+    Baseline,
+    /// The value is `dumb`, so [`TermCap::dumb`] was used
+    Dumb,
+}
+
+/// Prefixes of `$TERM` that are removed by [`GroupedTermCaps::lookup`]
+///
+/// Multiplexers use these to indicate the terminal they're running in.
+const TERM_PREFIXES: &[&str] = &["screen.", "tmux."];
+
+/// Suffixes of `$TERM` that are removed by [`GroupedTermCaps::lookup`], for variants of a
+/// terminal's entry that only differ in colors
+const TERM_SUFFIXES: &[&str] = &[
+    "-256color",
+    "-88color",
+    "-16color",
+    "-direct",
+    "-truecolor",
+    "-24bit",
+    "-color",
+];
+
+/// Families of terminals whose more general member isn't given by removing the last part of the
+/// name, for [`GroupedTermCaps::lookup`]
+const TERM_FAMILIES: &[(&str, &str)] = &[
+    ("vte", "xterm-256color"),
+    ("gnome", "xterm-256color"),
+    ("xfce", "xterm-256color"),
+];
+
 /// A [`TermCap`] with an associated [`TerminalName`]
 #[derive(Debug, Clone, Deserialize)]
 pub struct LabelledTermCap {
@@ -70,6 +186,20 @@ pub struct TermCap {
     pub cursor: CursorCap,
     /// Capabilities for scrolling content on the screen
     pub scroll: ScrollCap,
+    /// Capabilities for erasing content on the screen
+    pub erase: EraseCap,
+    /// Capabilities for inserting and deleting lines and characters
+    pub edit: EditCap,
+    /// Capabilities for toggling terminal modes
+    pub mode: ModeCap,
+    /// Capabilities for interacting with the terminal's window
+    pub window: WindowCap,
+    /// Capabilities for accessing the system clipboard
+    pub clipboard: ClipboardCap,
+    /// Capabilities for changing the terminal's color palette
+    pub palette: PaletteCap,
+    /// Capabilities for enhanced keyboard input
+    pub keyboard: KeyboardCap,
 }
 
 // helper function to deserialize "compact" terminal names -- disallowing certain characters
@@ -185,6 +315,29 @@ pub struct StyleCap {
     #[serde(alias = "unsetUnderline")]
     #[serde(alias = "unset-underline")]
     pub unset_underline: bool,
+
+    /// Strikethrough capabilities: `true` if enabled, `false` if disabled
+    ///
+    /// *Standard*: ECMA-48 2nd <br>
+    /// *Escape Sequence*: `ESC[9m`
+    #[serde(alias = "setStrikethrough")]
+    #[serde(alias = "set-strikethrough")]
+    pub set_strikethrough: bool,
+    /// Resetting strikethrough: `true` if enabled, `false` if disabled
+    ///
+    /// *Standard*: ECMA-48 3rd <br>
+    /// *Escape Sequence*: `ESC[29m`
+    #[serde(alias = "unsetStrikethrough")]
+    #[serde(alias = "unset-strikethrough")]
+    pub unset_strikethrough: bool,
+
+    /// Hyperlinks attached to text: `true` if enabled, `false` if disabled
+    ///
+    /// *Standard*: [Egmont Koblinger's spec] (de facto) <br>
+    /// *Escape Sequence*: `ESC]8;<PARAMS>;<URI>ESC\` (open) and `ESC]8;;ESC\` (close)
+    ///
+    /// [Egmont Koblinger's spec]: https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feda
+    pub hyperlinks: bool,
 }
 
 /// Capabilities for displaying colors
@@ -317,21 +470,22 @@ pub struct CursorCap {
     /// * `ESC[<N?>A` -- Cursor up `N` times (default 1)
     /// * `ESC[<N?>B` -- Cursor down `N` times (default 1)
     /// * `ESC[<N?>C` -- Cursor forward `N` times (default 1)
-    /// * `ESC[<N?>D` -- Cursor down `N` times (default 1)
+    /// * `ESC[<N?>D` -- Cursor back `N` times (default 1)
     /// * `ESC[<N?>E` -- Cursor down `N` times and to first column (default 1)
     /// * `ESC[<N?>F` -- Cursor up `N` times and to first column (default 1)
     /// * `ESC[<N?>G` -- Cursor to column `N` (default 1)
     /// * `ESC[<R?>;<C?>H` -- Cursor to position (`row;column`, default `1;1`)
+    /// * `ESC[<N?>d` -- Cursor to row `N` (default 1)
     ///
     /// *Standard*: ECMA-48
     #[serde(alias = "basicMovement")]
     #[serde(alias = "basic-movement")]
-    basic_movement: bool,
+    pub basic_movement: bool,
 
     /// Capabilities for setting the cursor's style
     #[serde(alias = "setStyle")]
     #[serde(alias = "set-style")]
-    set_style: CursorStyleCap,
+    pub set_style: CursorStyleCap,
 
     /// Capabilities for saving and restoring the cursor position
     ///
@@ -339,7 +493,13 @@ pub struct CursorCap {
     /// *Escape Sequence*: `ESC[s` (save) and `ESC[u` (restore)
     #[serde(alias = "saveAndRestore")]
     #[serde(alias = "save-and-restore")]
-    save_and_restore: bool,
+    pub save_and_restore: bool,
+
+    /// Capabilities for hiding and showing the cursor
+    ///
+    /// *Standard*: VT220 (DECTCEM) <br>
+    /// *Escape Sequence*: `ESC[?25l` (hide) and `ESC[?25h` (show)
+    pub visibility: bool,
 }
 
 /// Capabilities for setting the cursor's style
@@ -357,14 +517,14 @@ pub struct CursorStyleCap {
     /// *Standard*: VT520
     /// *Escape Sequence*: `ESC[<N?><SP>q` where `N` is one of: `0`/`1` (blink block), `2` (steady
     /// block), `3` (blink underline), or `4` (steady underline). The default is `1` (blink block).
-    basic: bool,
+    pub basic: bool,
     /// Xterm-extended cursor style settings
     ///
     /// *Standard*: Xterm
     /// *Escape Sequence*: `ESC[<N><SP>q` where `N` is either `5` (blink bar) or `6` (steady bar)
     #[serde(alias = "xterm-extended")]
     #[serde(alias = "xtermExtended")]
-    xterm_extended: bool,
+    pub xterm_extended: bool,
 }
 
 /// Capabilities for scrolling the terminal
@@ -374,9 +534,9 @@ pub struct ScrollCap {
     /// Basic scrolling capabilities (can it scroll the screen at all?)
     ///
     /// *Standard*: ECMA
-    /// *Escape Sequence*: `ESC[<N?>S` (scroll up, default: 1) and `ESC[<N?>^` (scroll down,
+    /// *Escape Sequence*: `ESC[<N?>S` (scroll up, default: 1) and `ESC[<N?>T` (scroll down,
     /// default: 1)
-    basic: bool,
+    pub basic: bool,
 
     /// Capabilities for setting a scroll region
     ///
@@ -384,16 +544,225 @@ pub struct ScrollCap {
     /// *Escape Sequence*: `ESC[<Top?>;<Bot?>r` (default: full size of window)
     #[serde(alias = "set-region")]
     #[serde(alias = "setRegion")]
-    set_region: bool,
+    pub set_region: bool,
+}
+
+/// Capabilities for erasing parts of the screen
+///
+/// All fields mark the capability as enabled if `true` and disabled if `false`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EraseCap {
+    /// Erasing within the cursor's line
+    ///
+    /// *Standard*: ECMA-48 <br>
+    /// *Escape Sequence*: `ESC[<N?>K` where `N` is one of: `0` (to end of line, the default), `1`
+    /// (to start of line), or `2` (entire line)
+    #[serde(alias = "inLine")]
+    #[serde(alias = "in-line")]
+    pub in_line: bool,
+    /// Erasing within the display
+    ///
+    /// *Standard*: ECMA-48 <br>
+    /// *Escape Sequence*: `ESC[<N?>J` where `N` is one of: `0` (to end of screen, the default),
+    /// `1` (to start of screen), or `2` (entire screen)
+    #[serde(alias = "inDisplay")]
+    #[serde(alias = "in-display")]
+    pub in_display: bool,
+    /// Erasing the scrollback buffer
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC[3J`
+    pub scrollback: bool,
+    /// Erasing some number of characters, starting from the cursor
+    ///
+    /// *Standard*: ECMA-48 <br>
+    /// *Escape Sequence*: `ESC[<N?>X` (default: 1)
+    pub characters: bool,
+}
+
+/// Capabilities for toggling terminal modes
+///
+/// All fields mark the capability as enabled if `true` and disabled if `false`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModeCap {
+    /// Switching to and from the alternate screen buffer
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC[?1049h` (enter) and `ESC[?1049l` (leave)
+    #[serde(alias = "alternateScreen")]
+    #[serde(alias = "alternate-screen")]
+    pub alternate_screen: bool,
+    /// Bracketed paste, where pasted text is surrounded by `ESC[200~` and `ESC[201~`
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC[?2004h` (enable) and `ESC[?2004l` (disable)
+    #[serde(alias = "bracketedPaste")]
+    #[serde(alias = "bracketed-paste")]
+    pub bracketed_paste: bool,
+    /// Focus reporting, where the terminal sends `ESC[I` and `ESC[O` when it gains or loses focus
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC[?1004h` (enable) and `ESC[?1004l` (disable)
+    #[serde(alias = "focusEvents")]
+    #[serde(alias = "focus-events")]
+    pub focus_events: bool,
+    /// Toggling automatic wrapping at the end of the line
+    ///
+    /// *Standard*: VT100 (DECAWM) <br>
+    /// *Escape Sequence*: `ESC[?7h` (enable) and `ESC[?7l` (disable)
+    pub autowrap: bool,
+    /// Synchronized output, where the terminal holds off on redrawing the screen until the update
+    /// is finished
+    ///
+    /// *Standard*: [Contour] (de facto) <br>
+    /// *Escape Sequence*: `ESC[?2026h` (begin) and `ESC[?2026l` (end)
+    ///
+    /// [Contour]: https://gist.github.com/christianparpart/d8a62cc1ab659194337d73e399004036
+    #[serde(alias = "synchronizedOutput")]
+    #[serde(alias = "synchronized-output")]
+    pub synchronized_output: bool,
+}
+
+/// Capabilities for interacting with the terminal's window (or tab)
+///
+/// All fields mark the capability as enabled if `true` and disabled if `false`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowCap {
+    /// Setting the window title
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC]2;<TITLE>ESC\` (or `ESC]0;<TITLE>ESC\` to also set the icon name)
+    #[serde(alias = "setTitle")]
+    #[serde(alias = "set-title")]
+    pub set_title: bool,
+    /// Setting the icon name, separately from the window title
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC]1;<NAME>ESC\`
+    #[serde(alias = "setIconName")]
+    #[serde(alias = "set-icon-name")]
+    pub set_icon_name: bool,
+    /// Saving and restoring the window title (and icon name) on a stack
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC[22;0t` (push) and `ESC[23;0t` (pop)
+    #[serde(alias = "titleStack")]
+    #[serde(alias = "title-stack")]
+    pub title_stack: bool,
+}
+
+/// Capabilities for accessing the system clipboard
+///
+/// Terminals often allow users to disable these (or require confirmation), for security reasons.
+/// Where that's the case, we record the default configuration.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClipboardCap {
+    /// Setting the contents of the clipboard: `true` if enabled, `false` if disabled
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC]52;<SELECTION>;<BASE64>ESC\`
+    pub copy: bool,
+    /// Requesting the contents of the clipboard: `true` if enabled, `false` if disabled
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC]52;<SELECTION>;?ESC\`, with the same form as `copy` for the reply
+    pub query: bool,
+    /// Maximum size of the base64-encoded payload when setting the clipboard, if there is one
+    ///
+    /// Some terminals silently truncate anything longer than their limit. The limit can also be
+    /// lowered to account for programs in between, like `tmux` or `screen`.
+    #[serde(alias = "maxPayload")]
+    #[serde(alias = "max-payload")]
+    pub max_payload: Option<usize>,
+}
+
+/// Capabilities for changing the terminal's color palette
+///
+/// All fields mark the capability as enabled if `true` and disabled if `false`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PaletteCap {
+    /// Setting and resetting entries in the 256-color palette
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC]4;<N>;<COLOR>ESC\` (set) and `ESC]104;<N?>ESC\` (reset, or reset
+    /// all if `N` is not given)
+    #[serde(alias = "setPalette")]
+    #[serde(alias = "set-palette")]
+    pub set_palette: bool,
+    /// Requesting the value of entries in the 256-color palette
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC]4;<N>;?ESC\`, with the same form as `set_palette` for the reply
+    #[serde(alias = "queryPalette")]
+    #[serde(alias = "query-palette")]
+    pub query_palette: bool,
+    /// Setting and resetting the default foreground, background, and cursor colors
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC]<N>;<COLOR>ESC\` (set) and `ESC]<N+100>ESC\` (reset) where `N`
+    /// is one of `10` (foreground), `11` (background), or `12` (cursor)
+    #[serde(alias = "setDynamic")]
+    #[serde(alias = "set-dynamic")]
+    pub set_dynamic: bool,
+    /// Requesting the default foreground, background, and cursor colors
+    ///
+    /// *Standard*: Xterm <br>
+    /// *Escape Sequence*: `ESC]<N>;?ESC\`, with the same form as `set_dynamic` for the reply
+    #[serde(alias = "queryDynamic")]
+    #[serde(alias = "query-dynamic")]
+    pub query_dynamic: bool,
+}
+
+/// Capabilities for enhanced keyboard input
+///
+/// All fields mark the capability as enabled if `true` and disabled if `false`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyboardCap {
+    /// The Kitty keyboard protocol, for progressive enhancement of how keys are reported
+    ///
+    /// *Standard*: [Kitty](https://sw.kovidgoyal.net/kitty/keyboard-protocol/) <br>
+    /// *Escape Sequence*: `ESC[><FLAGS>u` (push), `ESC[<<N>u` (pop), and `ESC[?u` (query, with
+    /// `ESC[?<FLAGS>u` as the reply)
+    #[serde(alias = "kittyProtocol")]
+    #[serde(alias = "kitty-protocol")]
+    pub kitty_protocol: bool,
+}
+
+/// Capabilities for inserting and deleting lines and characters, shifting the rest of the screen
+///
+/// All fields mark the capability as enabled if `true` and disabled if `false`.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EditCap {
+    /// Inserting and deleting lines at the cursor, within the scroll region
+    ///
+    /// *Standard*: VT102 <br>
+    /// *Escape Sequence*: `ESC[<N?>L` (insert, default: 1) and `ESC[<N?>M` (delete, default: 1)
+    pub lines: bool,
+    /// Inserting and deleting characters at the cursor, within the line
+    ///
+    /// *Standard*: VT102 (deleting) and VT220 (inserting) <br>
+    /// *Escape Sequence*: `ESC[<N?>@` (insert, default: 1) and `ESC[<N?>P` (delete, default: 1)
+    pub chars: bool,
 }
 
 /// Error occuring from loading a [`TermCapSet`]
+///
+/// The `Yaml` variant only exists with the `yaml` feature, so this can't be matched exhaustively.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum LoadTermCapsError {
     /// An error from failing to read the file
     #[error(transparent)]
     Io(#[from] io::Error),
     /// An error from failing to parse the content of the file
+    #[cfg(feature = "yaml")]
     #[error("Failed to parse YAML")]
     Yaml(
         #[source]
@@ -408,12 +777,32 @@ pub enum LoadTermCapsError {
 }
 
 impl TermCapSet {
+    /// Returns the default `TermCapSet`, from the `capdata.yaml` that's compiled into the crate
+    ///
+    /// The data is converted to Rust at build time, so this is available even without the `yaml`
+    /// feature.
+    pub fn builtin() -> Self {
+        let vec = include!(concat!(env!("OUT_DIR"), "/builtin_caps.rs"));
+        Self::from_vec(vec).expect("built-in capability data should be valid")
+    }
+
     /// Loads the `TermCapSet` from the file
+    #[cfg(feature = "yaml")]
     pub fn load_all_from_file(path: &Path) -> Result<Self, LoadTermCapsError> {
-        use std::collections::btree_map::Entry;
-
         let content = fs::read(path)?;
-        let vec: Vec<LabelledTermCap> = serde_yaml::from_slice(&content)?;
+        Self::load_all_from_slice(&content)
+    }
+
+    /// Parses the `TermCapSet` from the contents of a YAML file
+    #[cfg(feature = "yaml")]
+    fn load_all_from_slice(content: &[u8]) -> Result<Self, LoadTermCapsError> {
+        let vec: Vec<LabelledTermCap> = serde_yaml::from_slice(content)?;
+        Self::from_vec(vec)
+    }
+
+    /// Creates the `TermCapSet` from a list of terminals, checking that their names are unique
+    fn from_vec(vec: Vec<LabelledTermCap>) -> Result<Self, LoadTermCapsError> {
+        use std::collections::btree_map::Entry;
 
         let mut terminals = BTreeMap::new();
         let mut duplicates = Vec::new();
@@ -463,7 +852,7 @@ impl TermCapSet {
         for (k, cap) in by_name.iter() {
             match by_term_var.entry(cap.name.term.clone()) {
                 Entry::Vacant(e) => {
-                    let min_caps = cap.caps.clone();
+                    let min_caps = cap.caps;
                     let mut components = BTreeMap::new();
                     components.insert(k.clone(), Arc::clone(cap));
                     let _ = e.insert(TermCapGroup { min_caps, members: components });
@@ -497,6 +886,189 @@ impl GroupedTermCaps {
         self.by_name.get(compact_name)
     }
 
+    /// Identifies the current terminal from the environment
+    ///
+    /// Variables that are specific to a terminal are checked first: `$KITTY_WINDOW_ID`,
+    /// `$TERM_PROGRAM`, `$COLORTERM` (when it names a terminal, rather than e.g. `truecolor`), and
+    /// `$VTE_VERSION`. If none of them identifies a known terminal, we fall back to the group of
+    /// terminals matching the value of `$TERM` (see [`lookup`](Self::lookup)). `$TMUX` and `$STY`
+    /// are checked for multiplexers.
+    ///
+    /// Returns `None` if the terminal couldn't be identified at all -- including when `$TERM`
+    /// would only get the baseline capabilities, since it could be something like `dumb`.
+    pub fn detect(&self) -> Option<DetectedTermCap> {
+        self.detect_with(|name| std::env::var(name).ok())
+    }
+
+    /// Like [`detect`](Self::detect), but with the environment variables given by `var` instead of
+    /// the process's environment
+    pub fn detect_with(&self, var: impl Fn(&str) -> Option<String>) -> Option<DetectedTermCap> {
+        let var = |name| var(name).filter(|value| !value.is_empty());
+        let term = var("TERM");
+
+        let multiplexer = if var("TMUX").is_some() || var("TERM_PROGRAM").as_deref() == Some("tmux")
+        {
+            Some(Multiplexer::Tmux)
+        } else if var("STY").is_some() {
+            Some(Multiplexer::Screen)
+        } else {
+            None
+        };
+
+        let identified = None
+            .or_else(|| {
+                var("KITTY_WINDOW_ID")?;
+                let terminal = self.get_by_name("kitty")?;
+                Some((terminal, DetectionSignal::KittyWindowId, None))
+            })
+            .or_else(|| {
+                let terminal = self.find_by_name(&var("TERM_PROGRAM")?)?;
+                let version = var("TERM_PROGRAM_VERSION");
+                Some((terminal, DetectionSignal::TermProgram, version))
+            })
+            .or_else(|| {
+                let terminal = self.find_by_name(&var("COLORTERM")?)?;
+                Some((terminal, DetectionSignal::ColorTerm, None))
+            })
+            .or_else(|| {
+                let version = var("VTE_VERSION")?;
+                let terminal = self.get_by_name("libvte")?;
+                Some((terminal, DetectionSignal::VteVersion, Some(version)))
+            });
+
+        let mut detected = match identified {
+            Some((terminal, signal, version)) => {
+                // Variables like `$KITTY_WINDOW_ID` are inherited by other terminals started from
+                // inside the identified one, so we only trust them if `$TERM` agrees
+                let confidence = match term {
+                    Some(term) if term == terminal.name.term => Confidence::High,
+                    _ => Confidence::Medium,
+                };
+                DetectedTermCap {
+                    caps: terminal.caps,
+                    terminal: Some(Arc::clone(terminal)),
+                    signal,
+                    confidence,
+                    version,
+                    multiplexer,
+                }
+            }
+            None => {
+                let (group, matched) = self.lookup_group(&term?)?;
+                let mut members = group.members.values();
+                let (terminal, confidence) = match (members.next(), members.next()) {
+                    (Some(only), None) if matched == TermMatch::Exact => {
+                        (Some(Arc::clone(only)), Confidence::Medium)
+                    }
+                    _ => (None, Confidence::Low),
+                };
+                DetectedTermCap {
+                    caps: group.min_caps,
+                    terminal,
+                    signal: DetectionSignal::Term,
+                    confidence,
+                    version: None,
+                    multiplexer,
+                }
+            }
+        };
+
+        if multiplexer.is_some() {
+            detected.confidence = Confidence::Low;
+        }
+        Some(detected)
+    }
+
+    /// Returns the capabilities for a value of `$TERM`, even if it isn't exactly in the data
+    ///
+    /// If there's no exact match, a few things are tried in turn:
+    ///
+    /// 1. Removing multiplexer prefixes (like `screen.` in `screen.xterm-256color`) and color
+    ///    suffixes (like `-direct` or `-256color`), and then adding `-256color` back.
+    /// 2. Falling back through the terminal's family, like `xterm` for `xterm-new`, or
+    ///    `xterm-256color` for `vte`.
+    /// 3. Using the conservative [`TermCap::baseline`].
+    ///
+    /// `dumb` is handled specially, getting [`TermCap::dumb`] instead. The returned [`TermLookup`]
+    /// says which of these produced the match.
+    pub fn lookup(&self, term_env_var: &str) -> TermLookup {
+        if term_env_var == "dumb" {
+            return TermLookup { caps: TermCap::dumb(), matched: TermMatch::Dumb };
+        }
+
+        match self.lookup_group(term_env_var) {
+            Some((group, matched)) => TermLookup { caps: group.min_caps, matched },
+            None => TermLookup {
+                caps: TermCap::baseline(),
+                matched: TermMatch::Baseline,
+            },
+        }
+    }
+
+    /// Returns the group for `$TERM` and how it was matched, as in [`lookup`](Self::lookup) but
+    /// without the baseline
+    fn lookup_group(&self, term: &str) -> Option<(&TermCapGroup, TermMatch)> {
+        if let Some(group) = self.get(term) {
+            return Some((group, TermMatch::Exact));
+        }
+
+        let mut base = term;
+        while let Some(stripped) = TERM_PREFIXES.iter().find_map(|p| base.strip_prefix(p)) {
+            base = stripped;
+        }
+        loop {
+            if let Some((term, group)) = self.get_color_variant(base) {
+                return Some((group, TermMatch::Normalized { term: term.clone() }));
+            }
+            match TERM_SUFFIXES.iter().find_map(|s| base.strip_suffix(s)) {
+                Some(stripped) => base = stripped,
+                None => break,
+            }
+        }
+
+        // The chain is short in practice, but families could refer to each other
+        let mut name = base.to_owned();
+        for _ in 0..8 {
+            name = match name.rsplit_once('-') {
+                Some((parent, _)) => parent.to_owned(),
+                None => {
+                    let (_, parent) = TERM_FAMILIES.iter().find(|(family, _)| *family == name)?;
+                    parent.to_string()
+                }
+            };
+            if let Some((term, group)) = self.get_color_variant(&name) {
+                return Some((group, TermMatch::Family { term: term.clone() }));
+            }
+        }
+
+        None
+    }
+
+    /// Returns the group for `$TERM` equal to `base`, or `base` with `-256color` added, along with
+    /// the actual value
+    fn get_color_variant(&self, base: &str) -> Option<(&String, &TermCapGroup)> {
+        let with_suffix = format!("{base}-256color");
+        self.by_term_var
+            .get_key_value(base)
+            .or_else(|| self.by_term_var.get_key_value(&with_suffix))
+    }
+
+    /// Returns the terminal whose compact or pretty name matches `name`, ignoring case and
+    /// anything other than letters -- so that e.g. `xfce4-terminal` matches `xfce-terminal`
+    fn find_by_name(&self, name: &str) -> Option<&Arc<LabelledTermCap>> {
+        let normalize = |s: &str| -> String {
+            s.chars()
+                .filter(char::is_ascii_alphabetic)
+                .map(|c| c.to_ascii_lowercase())
+                .collect()
+        };
+
+        let name = normalize(name);
+        self.by_name.values().find(|terminal| {
+            normalize(&terminal.name.compact) == name || normalize(&terminal.name.pretty) == name
+        })
+    }
+
     /// Produces an iterator over all recognized values of the `$TERM` environment variable
     pub fn env_vars(&self) -> impl Iterator<Item = &str> {
         self.by_term_var.keys().map(|string| string.as_str())
@@ -521,12 +1093,139 @@ impl TermCapGroup {
 }
 
 impl TermCap {
+    /// Returns a conservative set of capabilities, for terminals that aren't otherwise known
+    ///
+    /// This roughly corresponds to a VT102 with the basic colors of ECMA-48, which essentially
+    /// every terminal in use today supports. Nothing beyond that is included, since it could
+    /// produce garbage on the screen if unsupported. For terminals that don't support escape
+    /// sequences at all, use [`dumb`](Self::dumb) instead.
+    pub fn baseline() -> Self {
+        TermCap {
+            style: StyleCap {
+                reset_all: true,
+                set_color: ColorCap::Fixed4Bit,
+                unset_color: true,
+                set_inverse: true,
+                unset_inverse: true,
+                set_italics: false,
+                unset_italics: false,
+                set_bold: true,
+                set_faint: false,
+                unset_bold_faint: true,
+                set_underline: UnderlineCap::Basic,
+                unset_underline: true,
+                set_strikethrough: false,
+                unset_strikethrough: false,
+                hyperlinks: false,
+            },
+            cursor: CursorCap {
+                basic_movement: true,
+                set_style: CursorStyleCap { basic: false, xterm_extended: false },
+                save_and_restore: true,
+                visibility: false,
+            },
+            scroll: ScrollCap { basic: false, set_region: false },
+            erase: EraseCap {
+                in_line: true,
+                in_display: true,
+                scrollback: false,
+                characters: false,
+            },
+            edit: EditCap { lines: true, chars: false },
+            mode: ModeCap {
+                alternate_screen: false,
+                bracketed_paste: false,
+                focus_events: false,
+                autowrap: true,
+                synchronized_output: false,
+            },
+            window: WindowCap {
+                set_title: false,
+                set_icon_name: false,
+                title_stack: false,
+            },
+            clipboard: ClipboardCap { copy: false, query: false, max_payload: None },
+            palette: PaletteCap {
+                set_palette: false,
+                query_palette: false,
+                set_dynamic: false,
+                query_dynamic: false,
+            },
+            keyboard: KeyboardCap { kitty_protocol: false },
+        }
+    }
+
+    /// Returns a set of capabilities with nothing supported, for terminals that don't understand
+    /// escape sequences at all -- like `TERM=dumb`, or output that isn't going to a terminal
+    pub fn dumb() -> Self {
+        TermCap {
+            style: StyleCap {
+                reset_all: false,
+                set_color: ColorCap::None,
+                unset_color: false,
+                set_inverse: false,
+                unset_inverse: false,
+                set_italics: false,
+                unset_italics: false,
+                set_bold: false,
+                set_faint: false,
+                unset_bold_faint: false,
+                set_underline: UnderlineCap::None,
+                unset_underline: false,
+                set_strikethrough: false,
+                unset_strikethrough: false,
+                hyperlinks: false,
+            },
+            cursor: CursorCap {
+                basic_movement: false,
+                set_style: CursorStyleCap { basic: false, xterm_extended: false },
+                save_and_restore: false,
+                visibility: false,
+            },
+            scroll: ScrollCap { basic: false, set_region: false },
+            erase: EraseCap {
+                in_line: false,
+                in_display: false,
+                scrollback: false,
+                characters: false,
+            },
+            edit: EditCap { lines: false, chars: false },
+            mode: ModeCap {
+                alternate_screen: false,
+                bracketed_paste: false,
+                focus_events: false,
+                autowrap: false,
+                synchronized_output: false,
+            },
+            window: WindowCap {
+                set_title: false,
+                set_icon_name: false,
+                title_stack: false,
+            },
+            clipboard: ClipboardCap { copy: false, query: false, max_payload: None },
+            palette: PaletteCap {
+                set_palette: false,
+                query_palette: false,
+                set_dynamic: false,
+                query_dynamic: false,
+            },
+            keyboard: KeyboardCap { kitty_protocol: false },
+        }
+    }
+
     /// Produces the `TermCap` corresponding to the minimum shared set of capabilities
     fn min(self, other: Self) -> Self {
         TermCap {
             style: self.style.min(other.style),
             cursor: self.cursor.min(other.cursor),
             scroll: self.scroll.min(other.scroll),
+            erase: self.erase.min(other.erase),
+            edit: self.edit.min(other.edit),
+            mode: self.mode.min(other.mode),
+            window: self.window.min(other.window),
+            clipboard: self.clipboard.min(other.clipboard),
+            palette: self.palette.min(other.palette),
+            keyboard: self.keyboard.min(other.keyboard),
         }
     }
 }
@@ -546,6 +1245,9 @@ impl StyleCap {
             unset_bold_faint: self.unset_bold_faint && other.unset_bold_faint,
             set_underline: self.set_underline.min(other.set_underline),
             unset_underline: self.unset_underline && other.unset_underline,
+            set_strikethrough: self.set_strikethrough && other.set_strikethrough,
+            unset_strikethrough: self.unset_strikethrough && other.unset_strikethrough,
+            hyperlinks: self.hyperlinks && other.hyperlinks,
         }
     }
 }
@@ -597,6 +1299,7 @@ impl CursorCap {
             basic_movement: self.basic_movement && other.basic_movement,
             set_style: self.set_style.min(other.set_style),
             save_and_restore: self.save_and_restore && other.save_and_restore,
+            visibility: self.visibility && other.visibility,
         }
     }
 }
@@ -618,3 +1321,183 @@ impl ScrollCap {
         }
     }
 }
+
+impl EraseCap {
+    fn min(self, other: Self) -> Self {
+        EraseCap {
+            in_line: self.in_line && other.in_line,
+            in_display: self.in_display && other.in_display,
+            scrollback: self.scrollback && other.scrollback,
+            characters: self.characters && other.characters,
+        }
+    }
+}
+
+impl ModeCap {
+    fn min(self, other: Self) -> Self {
+        ModeCap {
+            alternate_screen: self.alternate_screen && other.alternate_screen,
+            bracketed_paste: self.bracketed_paste && other.bracketed_paste,
+            focus_events: self.focus_events && other.focus_events,
+            autowrap: self.autowrap && other.autowrap,
+            synchronized_output: self.synchronized_output && other.synchronized_output,
+        }
+    }
+}
+
+impl WindowCap {
+    fn min(self, other: Self) -> Self {
+        WindowCap {
+            set_title: self.set_title && other.set_title,
+            set_icon_name: self.set_icon_name && other.set_icon_name,
+            title_stack: self.title_stack && other.title_stack,
+        }
+    }
+}
+
+impl ClipboardCap {
+    fn min(self, other: Self) -> Self {
+        let max_payload = match (self.max_payload, other.max_payload) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        };
+
+        ClipboardCap {
+            copy: self.copy && other.copy,
+            query: self.query && other.query,
+            max_payload,
+        }
+    }
+}
+
+impl PaletteCap {
+    fn min(self, other: Self) -> Self {
+        PaletteCap {
+            set_palette: self.set_palette && other.set_palette,
+            query_palette: self.query_palette && other.query_palette,
+            set_dynamic: self.set_dynamic && other.set_dynamic,
+            query_dynamic: self.query_dynamic && other.query_dynamic,
+        }
+    }
+}
+
+impl KeyboardCap {
+    fn min(self, other: Self) -> Self {
+        KeyboardCap {
+            kitty_protocol: self.kitty_protocol && other.kitty_protocol,
+        }
+    }
+}
+
+impl EditCap {
+    fn min(self, other: Self) -> Self {
+        EditCap {
+            lines: self.lines && other.lines,
+            chars: self.chars && other.chars,
+        }
+    }
+}
+
+/// Returns the capabilities of the terminal in `capdata.yaml` with the given compact name
+#[cfg(test)]
+pub(crate) fn test_caps(compact_name: &str) -> TermCap {
+    TermCapSet::builtin().terminals[compact_name].caps
+}
+
+#[cfg(all(test, feature = "yaml"))]
+#[test]
+fn test_builtin() {
+    let builtin = TermCapSet::builtin();
+    let loaded = TermCapSet::load_all_from_slice(include_bytes!("../capdata.yaml")).unwrap();
+
+    assert_eq!(
+        builtin.terminals.keys().collect::<Vec<_>>(),
+        loaded.terminals.keys().collect::<Vec<_>>()
+    );
+    for (name, terminal) in &builtin.terminals {
+        let expected = &loaded.terminals[name];
+        assert_eq!(format!("{terminal:?}"), format!("{expected:?}"));
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_detect() {
+    use std::collections::HashMap;
+
+    let grouped = TermCapSet::builtin().group_by_env_var();
+    let detect = |vars: &[(&str, &str)]| {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        grouped.detect_with(|name| vars.get(name).map(|v| v.to_string()))
+    };
+    let compact =
+        |detected: &DetectedTermCap| detected.terminal.as_ref().unwrap().name.compact.clone();
+
+    let kitty = detect(&[("TERM", "xterm-kitty"), ("KITTY_WINDOW_ID", "1")]).unwrap();
+    assert_eq!(compact(&kitty), "kitty");
+    assert_eq!(kitty.signal, DetectionSignal::KittyWindowId);
+    assert_eq!(kitty.confidence, Confidence::High);
+
+    // `xterm-256color` alone is ambiguous, but older VTE terminals set `$COLORTERM` to their name
+    let vte = detect(&[("TERM", "xterm-256color")]).unwrap();
+    assert!(vte.terminal.is_none());
+    assert_eq!(vte.signal, DetectionSignal::Term);
+    assert_eq!(vte.confidence, Confidence::Low);
+    let xfce = detect(&[("TERM", "xterm-256color"), ("COLORTERM", "xfce4-terminal")]).unwrap();
+    assert_eq!(compact(&xfce), "xfce-terminal");
+    assert_eq!(xfce.confidence, Confidence::High);
+    let vte = detect(&[("TERM", "xterm-256color"), ("VTE_VERSION", "7600")]).unwrap();
+    assert_eq!(compact(&vte), "libvte");
+    assert_eq!(vte.version.as_deref(), Some("7600"));
+
+    // Inherited from Kitty by a terminal that it started, and then inside tmux
+    let other = detect(&[("TERM", "alacritty"), ("KITTY_WINDOW_ID", "1")]).unwrap();
+    assert_eq!(other.confidence, Confidence::Medium);
+    let tmux = detect(&[
+        ("TERM", "tmux-256color"),
+        ("TMUX", "/tmp/x"),
+        ("KITTY_WINDOW_ID", "1"),
+    ]);
+    let tmux = tmux.unwrap();
+    assert_eq!(tmux.multiplexer, Some(Multiplexer::Tmux));
+    assert_eq!(tmux.confidence, Confidence::Low);
+
+    assert!(detect(&[("TERM", "dumb")]).is_none());
+}
+
+#[cfg(test)]
+#[test]
+fn test_lookup() {
+    let grouped = TermCapSet::builtin().group_by_env_var();
+    let matched = |term| grouped.lookup(term).matched;
+    let normalized = |term: &str| TermMatch::Normalized { term: term.to_owned() };
+    let family = |term: &str| TermMatch::Family { term: term.to_owned() };
+
+    assert_eq!(matched("xterm-kitty"), TermMatch::Exact);
+    assert_eq!(matched("xterm-direct"), normalized("xterm"));
+    assert_eq!(matched("alacritty-direct"), normalized("alacritty"));
+    assert_eq!(
+        matched("screen.xterm-256color"),
+        normalized("xterm-256color")
+    );
+    assert_eq!(matched("rxvt-unicode"), normalized("rxvt-unicode-256color"));
+    assert_eq!(matched("xterm-new"), family("xterm"));
+    assert_eq!(matched("vte"), family("xterm-256color"));
+    // Plain rxvt is a much older terminal than rxvt-unicode, so it doesn't get its capabilities
+    assert_eq!(matched("rxvt"), TermMatch::Baseline);
+    assert_eq!(matched("linux"), TermMatch::Baseline);
+    assert_eq!(matched("dumb"), TermMatch::Dumb);
+
+    let caps = grouped.lookup("vt100").caps;
+    assert!(caps.cursor.basic_movement);
+    assert!(!caps.mode.alternate_screen);
+
+    // Nothing is written with the capabilities for `dumb`
+    let caps = grouped.lookup("dumb").caps;
+    let mut out = Vec::new();
+    let style = crate::Style::new().bold(true);
+    crate::Command::SetStyle(style)
+        .write_to(&mut out, &caps)
+        .unwrap();
+    assert_eq!(out, b"");
+}
@@ -0,0 +1,172 @@
+//! System clipboard access, via OSC 52
+//!
+//! Clipboard contents are base64-encoded in both directions. Because this works entirely through
+//! escape sequences, it also works over SSH -- unlike talking to the system clipboard directly.
+
+use std::io;
+
+/// A selection that can be used with [`ClipboardCommand`](crate::ClipboardCommand)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClipboardSelection {
+    /// The system clipboard -- i.e., what's typically used by "copy" and "paste"
+    Clipboard,
+    /// The primary selection, as in X11 (usually, the most recently selected text)
+    Primary,
+    /// The secondary selection, as in X11
+    Secondary,
+}
+
+/// A reply from the terminal to [`ClipboardCommand::Query`](crate::ClipboardCommand::Query)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardReply {
+    /// The selection that the contents came from
+    pub selection: ClipboardSelection,
+    /// The contents of the selection
+    ///
+    /// Anything that isn't valid UTF-8 is replaced by `U+FFFD REPLACEMENT CHARACTER`.
+    pub text: String,
+}
+
+impl ClipboardSelection {
+    fn as_char(self) -> char {
+        match self {
+            ClipboardSelection::Clipboard => 'c',
+            ClipboardSelection::Primary => 'p',
+            ClipboardSelection::Secondary => 's',
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            b'c' => Some(ClipboardSelection::Clipboard),
+            b'p' => Some(ClipboardSelection::Primary),
+            b's' => Some(ClipboardSelection::Secondary),
+            _ => None,
+        }
+    }
+}
+
+impl ClipboardReply {
+    /// Parses the terminal's reply to a clipboard query
+    ///
+    /// The reply has the form `ESC]52;<SELECTION>;<BASE64>` followed by either `ESC\` or `BEL`.
+    /// The terminator may be omitted. Returns `None` if the reply is malformed.
+    pub fn parse(reply: &[u8]) -> Option<Self> {
+        let body = reply.strip_prefix(b"\x1b]")?;
+        let body = body
+            .strip_suffix(b"\x1b\\")
+            .or_else(|| body.strip_suffix(b"\x07"))
+            .unwrap_or(body);
+
+        Self::parse_osc_body(body)
+    }
+
+    /// Parses the reply from the body of the OSC sequence -- i.e., everything after `ESC]` and
+    /// before the terminator
+    pub(crate) fn parse_osc_body(body: &[u8]) -> Option<Self> {
+        let body = body.strip_prefix(b"52;")?;
+        // Terminals may report multiple selections (e.g. `pc`); we only use the first one.
+        let (&selection, rest) = body.split_first()?;
+        let data = &rest[rest.iter().position(|&b| b == b';')? + 1..];
+
+        let selection = ClipboardSelection::from_byte(selection)?;
+        let text = String::from_utf8_lossy(&base64_decode(data)?).into_owned();
+        Some(ClipboardReply { selection, text })
+    }
+}
+
+/// Writes the escape sequence that sets the contents of the selection, given the base64-encoded
+/// text
+pub(crate) fn write_copy<W: io::Write + ?Sized>(
+    out: &mut W,
+    selection: ClipboardSelection,
+    encoded: &str,
+) -> io::Result<()> {
+    write!(out, "\x1b]52;{};{encoded}\x1b\\", selection.as_char())
+}
+
+/// Writes the escape sequence that requests the contents of the selection
+pub(crate) fn write_query<W: io::Write + ?Sized>(
+    out: &mut W,
+    selection: ClipboardSelection,
+) -> io::Result<()> {
+    write!(out, "\x1b]52;{};?\x1b\\", selection.as_char())
+}
+
+static BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes the bytes with standard, padded base64
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => s.push(BASE64_ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char),
+                false => s.push('='),
+            }
+        }
+    }
+    s
+}
+
+/// Decodes standard base64, with or without padding
+///
+/// Returns `None` if there are any invalid characters.
+pub(crate) fn base64_decode(s: &[u8]) -> Option<Vec<u8>> {
+    let s = match s.iter().position(|&b| b == b'=') {
+        Some(i) if s[i..].iter().all(|&b| b == b'=') => &s[..i],
+        Some(_) => return None,
+        None => s,
+    };
+
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3 + 2);
+    for chunk in s.chunks(4) {
+        let mut n = 0u32;
+        for (i, &b) in chunk.iter().enumerate() {
+            let v = BASE64_ALPHABET.iter().position(|&a| a == b)? as u32;
+            n |= v << (18 - 6 * i);
+        }
+        match chunk.len() {
+            1 => return None,
+            len => bytes.extend_from_slice(&n.to_be_bytes()[1..len]),
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+#[test]
+fn test_base64() {
+    for (raw, encoded) in [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+    ] {
+        assert_eq!(base64_encode(raw.as_bytes()), encoded);
+        assert_eq!(base64_decode(encoded.as_bytes()).unwrap(), raw.as_bytes());
+    }
+
+    assert_eq!(base64_decode(b"Zm9vYg").unwrap(), b"foob");
+    assert_eq!(base64_decode(b"Zm9v!"), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_reply() {
+    let reply = ClipboardReply::parse(b"\x1b]52;c;aGVsbG8=\x07").unwrap();
+    assert_eq!(reply.selection, ClipboardSelection::Clipboard);
+    assert_eq!(reply.text, "hello");
+
+    let reply = ClipboardReply::parse(b"\x1b]52;p;\x1b\\").unwrap();
+    assert_eq!(reply.selection, ClipboardSelection::Primary);
+    assert_eq!(reply.text, "");
+
+    assert_eq!(ClipboardReply::parse(b"\x1b]52;x;aGVsbG8="), None);
+}
@@ -1,18 +1,42 @@
 //! Terminal commands
 
+use crate::capinfo::TermCap;
+use crate::clipboard::{self, ClipboardSelection};
+use crate::keyboard::KeyboardFlags;
+use crate::motion;
+use crate::palette::PaletteEntry;
+use crate::style::SgrParams;
+use crate::{Hyperlink, Style};
+use std::io;
 use std::ops::Range;
+use thiserror::Error;
 
 /// All terminal commands that can be represented
 ///
 /// This type exists only so that there's a unified place for all the various terminal commands. As
 /// such, it is marked non-exhaustive, so that we may add more fields to it without necessitating a
 /// major version bump.
+///
+/// Commands are turned into escape sequences with [`write_to`](Self::write_to), which picks the
+/// appropriate sequence for the terminal's capabilities.
+#[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum Command {
     /// Set the current style
     ///
     /// Resetting all styles is accomplished by `SetStyle(Style::default())`.
-    SetStyle(crate::Style),
+    SetStyle(Style),
+    /// Change the current style from `from` to `to`
+    ///
+    /// Unlike `SetStyle`, this assumes that `from` is the style currently in use, so only the
+    /// attributes that differ are changed -- producing much shorter escape sequences when the two
+    /// styles are similar.
+    #[allow(missing_docs)]
+    ChangeStyle { from: Style, to: Style },
+    /// Set the hyperlink attached to any text that's written, or remove it if `None`
+    ///
+    /// If the terminal doesn't support hyperlinks, this command does nothing.
+    SetHyperlink(Option<Hyperlink>),
 
     /// Moves or otherwise interacts with the cursor
     Cursor(CursorCommand),
@@ -21,9 +45,35 @@ pub enum Command {
 
     /// Erases a region of the screen
     Erase(EraseCommand),
+    /// Inserts or deletes lines or characters
+    Edit(EditCommand),
+
+    /// Enables or disables a terminal mode
+    #[allow(missing_docs)]
+    SetMode { mode: Mode, enabled: bool },
+    /// Requests whether a terminal mode is enabled, with a reply parsed as [`ModeReport`]
+    ///
+    /// The reply also tells whether the terminal supports the mode at all. Terminals that don't
+    /// support the request ignore it, so it's always written.
+    ///
+    /// [`ModeReport`]: crate::ModeReport
+    QueryMode(Mode),
+
+    /// Interacts with the terminal's window title or icon name
+    Window(WindowCommand),
+
+    /// Interacts with the system clipboard
+    Clipboard(ClipboardCommand),
+
+    /// Changes or requests the terminal's color palette
+    Palette(PaletteCommand),
+
+    /// Changes or requests the enhancements to keyboard input
+    Keyboard(KeyboardCommand),
 }
 
 /// A command that moves the cursor
+#[derive(Debug, Clone)]
 pub enum CursorCommand {
     /// Move the cursor the given X and Y coordinates
     ///
@@ -41,6 +91,20 @@ pub enum CursorCommand {
     #[allow(missing_docs)]
     MoveRelative { x: i16, y: i16 },
 
+    /// Moves the cursor from a known position to another, using the shortest sequence available
+    ///
+    /// Depending on the distance, this may use any mix of absolute and relative movement, or
+    /// control characters like `\r` and `\b`. Where control characters suffice, this works even
+    /// if the terminal doesn't support [`basic_movement`].
+    ///
+    /// Positions are given as `(x, y)`, with the top-left corner at (0, 0). This assumes that the
+    /// scroll region covers the entire screen, and that the cursor isn't waiting to wrap after
+    /// printing in the last column.
+    ///
+    /// [`basic_movement`]: crate::capinfo::CursorCap::basic_movement
+    #[allow(missing_docs)]
+    MoveFrom { from: (u16, u16), to: (u16, u16) },
+
     /// Move the cursor to the bottom-left corner of the screen
     MoveToBottomLeft,
 
@@ -54,18 +118,1001 @@ pub enum CursorCommand {
     /// The position must have been saved with `SavePosition` before it can be restored by this
     /// command.
     RestorePosition,
+
+    /// Sets the shape of the cursor, and whether it blinks
+    ///
+    /// If the terminal doesn't support the shape, the closest available one is used instead --
+    /// i.e., bars become underlines. If it doesn't support setting the shape at all, this command
+    /// does nothing.
+    #[allow(missing_docs)]
+    SetShape { shape: CursorShape, blink: bool },
+
+    /// Resets the shape of the cursor to the terminal's default
+    ResetShape,
+
+    /// Hides the cursor
+    ///
+    /// If the terminal doesn't support hiding the cursor, this command does nothing.
+    Hide,
+
+    /// Shows the cursor, if it was hidden
+    Show,
+}
+
+/// The shape of the cursor, for [`CursorCommand::SetShape`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CursorShape {
+    /// A block covering the entire cell
+    Block,
+    /// A line underneath the cell
+    Underline,
+    /// A thin vertical bar on the left side of the cell
+    ///
+    /// This shape is an Xterm extension. Terminals that don't support it will use `Underline`
+    /// instead.
+    Bar,
 }
 
 /// A command that scrolls the contents of the screen
+///
+/// A positive `amount` scrolls the contents *up* -- i.e., lines disappear off the top of the
+/// screen and blank lines are added at the bottom, as if moving further down in a document.
+#[derive(Debug, Clone)]
 pub enum ScrollCommand {
     /// Scrolls the entire screen by the given amount
     #[allow(missing_docs)]
     FullScreen { amount: i16 },
 
     /// Scrolls a region of the screen by the given amount
+    ///
+    /// The range of rows is zero-indexed, with an exclusive upper bound. Setting the scroll region
+    /// moves the cursor, so it will be left at the top-left corner of the screen afterwards.
+    ///
+    /// If the terminal can't set the scroll region, this is emulated by deleting lines on one side
+    /// of the region and inserting them on the other, as long as it supports [`EditCap::lines`].
+    ///
+    /// [`EditCap::lines`]: crate::capinfo::EditCap::lines
     #[allow(missing_docs)]
     Bounded { y_range: Range<u16>, amount: i16 },
 }
 
 /// A command that erases part of the screen
-pub enum EraseCommand {}
+///
+/// None of the erase commands move the cursor. Erased cells are typically filled with the current
+/// background color, so you may want to reset the style first.
+#[derive(Debug, Clone)]
+pub enum EraseCommand {
+    /// Erases from the cursor to the end of the line, including the cursor's position
+    ToLineEnd,
+    /// Erases from the start of the line to the cursor, including the cursor's position
+    ToLineStart,
+    /// Erases the entire line that the cursor is on
+    Line,
+
+    /// Erases from the cursor to the end of the screen, including the cursor's position
+    Below,
+    /// Erases from the start of the screen to the cursor, including the cursor's position
+    Above,
+    /// Erases the entire screen
+    Screen,
+    /// Erases the scrollback buffer, leaving the contents of the screen as-is
+    Scrollback,
+
+    /// Erases `count` characters, starting at the cursor and moving rightwards
+    ///
+    /// Characters beyond the end of the line are not erased.
+    #[allow(missing_docs)]
+    Characters { count: u16 },
+}
+
+/// A command that inserts or deletes lines or characters, shifting the rest of the screen
+///
+/// Inserted lines and characters are blank, filled with the current background color as with
+/// erasing. Anything pushed past the bottom of the screen or the end of the line is lost.
+#[derive(Debug, Clone)]
+pub enum EditCommand {
+    /// Inserts `count` blank lines at row `y`, pushing the lines below it down
+    ///
+    /// The cursor is left at the start of row `y`. If the terminal can't insert lines, this is
+    /// emulated by scrolling everything below `y`, as long as it supports
+    /// [`ScrollCap::set_region`]. Either way, this assumes the scroll region covers the entire
+    /// screen.
+    ///
+    /// [`ScrollCap::set_region`]: crate::capinfo::ScrollCap::set_region
+    #[allow(missing_docs)]
+    InsertLines { y: u16, count: u16 },
+    /// Deletes `count` lines starting at row `y`, pulling the lines below up and adding blank lines
+    /// at the bottom of the screen
+    ///
+    /// The cursor is left at the start of row `y`. As with `InsertLines`, this is emulated by
+    /// scrolling if the terminal can't delete lines.
+    #[allow(missing_docs)]
+    DeleteLines { y: u16, count: u16 },
+    /// Inserts `count` blank characters at the cursor, pushing the rest of the line right
+    ///
+    /// The cursor doesn't move.
+    #[allow(missing_docs)]
+    InsertCharacters { count: u16 },
+    /// Deletes `count` characters at the cursor, pulling the rest of the line left
+    ///
+    /// The cursor doesn't move.
+    #[allow(missing_docs)]
+    DeleteCharacters { count: u16 },
+}
+
+/// A command that sets the window title or icon name
+///
+/// Titles are sanitized before they are written: control characters are removed, so that they
+/// can't end the escape sequence early. If the terminal doesn't support the command, it does
+/// nothing.
+#[derive(Debug, Clone)]
+pub enum WindowCommand {
+    /// Sets the title of the window (or tab)
+    SetTitle(String),
+    /// Sets the icon name of the window
+    ///
+    /// Many terminals don't distinguish between the icon name and the title, and so won't support
+    /// this command.
+    SetIconName(String),
+    /// Sets both the title and icon name of the window
+    ///
+    /// If the terminal doesn't support setting the icon name, only the title is set.
+    SetTitleAndIconName(String),
+    /// Saves the current title and icon name, so that they can be restored with `PopTitle`
+    ///
+    /// Typically, this is used on startup to restore the user's title on exit.
+    PushTitle,
+    /// Restores the title and icon name saved by the last `PushTitle`
+    PopTitle,
+}
+
+/// A command that accesses the system clipboard
+#[derive(Debug, Clone)]
+pub enum ClipboardCommand {
+    /// Copies the text to the selection
+    ///
+    /// If the encoded text is larger than the terminal's [`max_payload`], this command fails
+    /// instead of letting the terminal truncate it.
+    ///
+    /// [`max_payload`]: crate::capinfo::ClipboardCap::max_payload
+    #[allow(missing_docs)]
+    Copy {
+        selection: ClipboardSelection,
+        text: String,
+    },
+    /// Requests the contents of the selection
+    ///
+    /// The terminal's response can be parsed with [`ClipboardReply::parse`].
+    ///
+    /// [`ClipboardReply::parse`]: crate::ClipboardReply::parse
+    #[allow(missing_docs)]
+    Query { selection: ClipboardSelection },
+}
+
+/// A command that changes or requests the terminal's color palette
+///
+/// Typically, this is used to apply a theme to the 16 named colors (and default foreground and
+/// background), restoring the user's palette on exit.
+#[derive(Debug, Clone)]
+pub enum PaletteCommand {
+    /// Sets the palette entry to the color
+    ///
+    /// [`Color::Fixed`](crate::Color::Fixed) values are converted to RGB, assuming Xterm's default
+    /// palette.
+    #[allow(missing_docs)]
+    Set {
+        entry: PaletteEntry,
+        color: crate::Color,
+    },
+    /// Requests the current value of the palette entry
+    ///
+    /// The terminal's response can be parsed with [`PaletteReply::parse`].
+    ///
+    /// [`PaletteReply::parse`]: crate::PaletteReply::parse
+    #[allow(missing_docs)]
+    Query { entry: PaletteEntry },
+    /// Resets the palette entry to the terminal's default
+    #[allow(missing_docs)]
+    Reset { entry: PaletteEntry },
+    /// Resets all 256 indexed colors to the terminal's defaults
+    ResetAllIndexed,
+}
+
+/// A command that uses the [Kitty keyboard protocol] to change or request how keyboard input is
+/// encoded
+///
+/// The terminal keeps a stack of [`KeyboardFlags`], where the top entry is the one in effect.
+/// Programs should push their flags on startup and pop them on exit, so that the previous flags
+/// are restored. The terminal maintains separate stacks for the normal and alternate screens.
+///
+/// [Kitty keyboard protocol]: https://sw.kovidgoyal.net/kitty/keyboard-protocol/
+#[derive(Debug, Clone)]
+pub enum KeyboardCommand {
+    /// Pushes the flags onto the stack, enabling them
+    PushFlags(KeyboardFlags),
+    /// Pops the given number of entries from the stack, restoring the flags from before they were
+    /// pushed
+    PopFlags(u16),
+    /// Requests the flags that are currently enabled
+    ///
+    /// The terminal's response can be parsed with [`KeyboardReply::parse`].
+    ///
+    /// [`KeyboardReply::parse`]: crate::KeyboardReply::parse
+    QueryFlags,
+}
+
+/// A terminal mode that can be toggled with [`Command::SetMode`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Mode {
+    /// The alternate screen buffer
+    ///
+    /// Full-screen applications typically use the alternate screen so that the contents of the
+    /// normal screen (and its scrollback) are restored when they exit. Entering the alternate
+    /// screen also saves the cursor position, which is restored on leaving.
+    AlternateScreen,
+    /// Bracketed paste, where the terminal surrounds pasted text with `ESC[200~` and `ESC[201~`
+    ///
+    /// This allows pasted text to be distinguished from typed text.
+    BracketedPaste,
+    /// Focus reporting, where the terminal sends `ESC[I` when it gains focus and `ESC[O` when it
+    /// loses focus
+    FocusEvents,
+    /// Automatic wrapping to the next line when text is written past the end of the line
+    ///
+    /// This is enabled by default in essentially all terminals.
+    Autowrap,
+    /// Synchronized output, where the terminal holds off on redrawing the screen until the mode is
+    /// disabled again
+    ///
+    /// Typically, this is used through [`SyncedUpdate`](crate::SyncedUpdate), which only enables
+    /// it if the terminal supports it.
+    SynchronizedOutput,
+}
+
+impl Mode {
+    /// Every mode, in the order they're declared
+    pub(crate) const ALL: [Mode; 5] = [
+        Mode::AlternateScreen,
+        Mode::BracketedPaste,
+        Mode::FocusEvents,
+        Mode::Autowrap,
+        Mode::SynchronizedOutput,
+    ];
+
+    /// Returns the number that identifies the (DEC private) mode in escape sequences
+    pub(crate) fn number(self) -> u16 {
+        match self {
+            Mode::AlternateScreen => 1049,
+            Mode::BracketedPaste => 2004,
+            Mode::FocusEvents => 1004,
+            Mode::Autowrap => 7,
+            Mode::SynchronizedOutput => 2026,
+        }
+    }
+}
+
+/// Error resulting from writing a [`Command`]
+#[derive(Debug, Error)]
+pub enum WriteCommandError {
+    /// An error from the underlying writer
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The terminal does not have the capabilities required by the command
+    ///
+    /// Commands that are purely cosmetic (e.g., styling) never produce this error; instead, they
+    /// degrade to whatever the terminal is capable of.
+    #[error("Terminal does not support {0}")]
+    Unsupported(&'static str),
+    /// The command's payload is larger than the terminal accepts
+    #[error("Payload of {size} bytes is larger than the maximum of {max}")]
+    PayloadTooLarge {
+        /// The size of the payload, in bytes
+        size: usize,
+        /// The maximum size accepted by the terminal, in bytes
+        max: usize,
+    },
+}
+
+impl Command {
+    /// Writes the escape sequence for the command, as supported by the terminal's capabilities
+    pub fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        match self {
+            Command::SetStyle(style) => {
+                let mut params = SgrParams::new();
+                style.push_set_params(&caps.style, &mut params);
+                params.write_to(out)?;
+                Ok(())
+            }
+            Command::ChangeStyle { from, to } => {
+                let mut params = SgrParams::new();
+                Style::push_transition_params(from, to, &caps.style, &mut params);
+                params.write_to(out)?;
+                Ok(())
+            }
+            Command::SetHyperlink(_) if !caps.style.hyperlinks => Ok(()),
+            Command::SetHyperlink(Some(link)) => Ok(link.write_open(out)?),
+            Command::SetHyperlink(None) => Ok(Hyperlink::write_close(out)?),
+            Command::Window(cmd) => cmd.write_to(out, caps),
+            Command::Clipboard(cmd) => cmd.write_to(out, caps),
+            Command::Palette(cmd) => cmd.write_to(out, caps),
+            Command::Keyboard(cmd) => cmd.write_to(out, caps),
+            Command::Cursor(cmd) => cmd.write_to(out, caps),
+            Command::Scroll(cmd) => cmd.write_to(out, caps),
+            Command::Erase(cmd) => cmd.write_to(out, caps),
+            Command::Edit(cmd) => cmd.write_to(out, caps),
+            Command::SetMode { mode, enabled } => {
+                let (supported, name) = match mode {
+                    Mode::AlternateScreen => (caps.mode.alternate_screen, "the alternate screen"),
+                    Mode::BracketedPaste => (caps.mode.bracketed_paste, "bracketed paste"),
+                    Mode::FocusEvents => (caps.mode.focus_events, "focus events"),
+                    Mode::Autowrap => (caps.mode.autowrap, "toggling autowrap"),
+                    Mode::SynchronizedOutput => {
+                        (caps.mode.synchronized_output, "synchronized output")
+                    }
+                };
+
+                if !supported {
+                    return Err(WriteCommandError::Unsupported(name));
+                }
+
+                let suffix = if *enabled { 'h' } else { 'l' };
+                write!(out, "\x1b[?{}{suffix}", mode.number())?;
+                Ok(())
+            }
+            Command::QueryMode(mode) => Ok(write!(out, "\x1b[?{}$p", mode.number())?),
+        }
+    }
+}
+
+impl CursorCommand {
+    fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        match self {
+            CursorCommand::SavePosition | CursorCommand::RestorePosition
+                if !caps.cursor.save_and_restore =>
+            {
+                return Err(WriteCommandError::Unsupported("saving the cursor position"))
+            }
+            CursorCommand::SavePosition | CursorCommand::RestorePosition => (),
+            CursorCommand::SetShape { .. } | CursorCommand::ResetShape => (),
+            CursorCommand::Hide | CursorCommand::Show => (),
+            CursorCommand::MoveFrom { .. } => (),
+            _ if !caps.cursor.basic_movement => {
+                return Err(WriteCommandError::Unsupported("cursor movement"))
+            }
+            _ => (),
+        }
+
+        match *self {
+            CursorCommand::MoveTo { x: Some(0), y: Some(0) } => write!(out, "\x1b[H")?,
+            CursorCommand::MoveTo { x: Some(x), y: Some(y) } => {
+                write!(out, "\x1b[{};{}H", y as u32 + 1, x as u32 + 1)?
+            }
+            CursorCommand::MoveTo { x: Some(x), y: None } => {
+                write_csi_count(out, x as u32 + 1, 'G')?
+            }
+            CursorCommand::MoveTo { x: None, y: Some(y) } => {
+                write_csi_count(out, y as u32 + 1, 'd')?
+            }
+            CursorCommand::MoveTo { x: None, y: None } => (),
+            CursorCommand::MoveRelative { x, y } => {
+                match x {
+                    0 => (),
+                    1.. => write_csi_count(out, x.unsigned_abs() as u32, 'C')?,
+                    _ => write_csi_count(out, x.unsigned_abs() as u32, 'D')?,
+                }
+                match y {
+                    0 => (),
+                    1.. => write_csi_count(out, y.unsigned_abs() as u32, 'B')?,
+                    _ => write_csi_count(out, y.unsigned_abs() as u32, 'A')?,
+                }
+            }
+            CursorCommand::MoveFrom { from, to } => {
+                out.write_all(&motion::cheapest_motion(from, to, caps)?)?
+            }
+            // Terminals clamp the cursor position to the size of the screen, so we can just pick
+            // a very large row.
+            CursorCommand::MoveToBottomLeft => write!(out, "\x1b[9999H")?,
+            CursorCommand::SavePosition => write!(out, "\x1b[s")?,
+            CursorCommand::RestorePosition => write!(out, "\x1b[u")?,
+            CursorCommand::SetShape { shape, blink } => {
+                let style = caps.cursor.set_style;
+                let shape = match shape {
+                    CursorShape::Bar if style.xterm_extended => Some(CursorShape::Bar),
+                    CursorShape::Bar | CursorShape::Underline if style.basic => {
+                        Some(CursorShape::Underline)
+                    }
+                    CursorShape::Block if style.basic => Some(CursorShape::Block),
+                    _ => None,
+                };
+
+                let n = match shape {
+                    None => return Ok(()),
+                    Some(CursorShape::Block) => 2,
+                    Some(CursorShape::Underline) => 4,
+                    Some(CursorShape::Bar) => 6,
+                };
+                write!(out, "\x1b[{} q", n - blink as u8)?
+            }
+            CursorCommand::ResetShape if caps.cursor.set_style.basic => write!(out, "\x1b[0 q")?,
+            CursorCommand::Hide if caps.cursor.visibility => write!(out, "\x1b[?25l")?,
+            CursorCommand::Show if caps.cursor.visibility => write!(out, "\x1b[?25h")?,
+            CursorCommand::ResetShape | CursorCommand::Hide | CursorCommand::Show => (),
+        }
+
+        Ok(())
+    }
+}
+
+impl ScrollCommand {
+    fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        let native = caps.scroll.basic && caps.scroll.set_region;
+        match self {
+            ScrollCommand::FullScreen { .. } if !caps.scroll.basic => {
+                return Err(WriteCommandError::Unsupported("scrolling"));
+            }
+            ScrollCommand::FullScreen { amount } => write_scroll(out, *amount)?,
+            ScrollCommand::Bounded { .. } if !native && !caps.edit.lines => {
+                let name = match caps.scroll.basic {
+                    true => "setting the scroll region",
+                    false => "scrolling",
+                };
+                return Err(WriteCommandError::Unsupported(name));
+            }
+            ScrollCommand::Bounded { y_range, amount } if y_range.is_empty() || *amount == 0 => (),
+            ScrollCommand::Bounded { y_range, amount } if native => {
+                write!(out, "\x1b[{};{}r", y_range.start as u32 + 1, y_range.end)?;
+                write_scroll(out, *amount)?;
+                write!(out, "\x1b[r")?;
+            }
+            ScrollCommand::Bounded { y_range, amount } => {
+                // Deleting lines at one end of the region pulls up everything below it, which is
+                // then pushed back down by inserting lines at the other end.
+                let count = amount.unsigned_abs().min(y_range.end - y_range.start);
+                let (delete_at, insert_at) = match *amount > 0 {
+                    true => (y_range.start, y_range.end - count),
+                    false => (y_range.end - count, y_range.start),
+                };
+                EditCommand::DeleteLines { y: delete_at, count }.write_to(out, caps)?;
+                EditCommand::InsertLines { y: insert_at, count }.write_to(out, caps)?;
+                write!(out, "\x1b[H")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl EditCommand {
+    fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        match *self {
+            EditCommand::InsertLines { count: 0, .. }
+            | EditCommand::DeleteLines { count: 0, .. } => Ok(()),
+            EditCommand::InsertLines { y, count } | EditCommand::DeleteLines { y, count } => {
+                let insert = matches!(self, EditCommand::InsertLines { .. });
+                let start_of_line = CursorCommand::MoveTo { x: Some(0), y: Some(y) };
+                if caps.edit.lines {
+                    Command::Cursor(start_of_line).write_to(out, caps)?;
+                    write_csi_count(out, count as u32, if insert { 'L' } else { 'M' })?;
+                } else if caps.scroll.basic && caps.scroll.set_region {
+                    // Leaving out the bottom of the scroll region defaults to the bottom of the
+                    // screen, so we don't need to know its height.
+                    let amount = count.min(i16::MAX as u16) as i16;
+                    write!(out, "\x1b[{}r", y as u32 + 1)?;
+                    write_scroll(out, if insert { -amount } else { amount })?;
+                    write!(out, "\x1b[r")?;
+                    Command::Cursor(start_of_line).write_to(out, caps)?;
+                } else {
+                    return Err(WriteCommandError::Unsupported(
+                        "inserting and deleting lines",
+                    ));
+                }
+                Ok(())
+            }
+            EditCommand::InsertCharacters { .. } | EditCommand::DeleteCharacters { .. }
+                if !caps.edit.chars =>
+            {
+                Err(WriteCommandError::Unsupported(
+                    "inserting and deleting characters",
+                ))
+            }
+            EditCommand::InsertCharacters { count: 0 }
+            | EditCommand::DeleteCharacters { count: 0 } => Ok(()),
+            EditCommand::InsertCharacters { count } => Ok(write_csi_count(out, count as u32, '@')?),
+            EditCommand::DeleteCharacters { count } => Ok(write_csi_count(out, count as u32, 'P')?),
+        }
+    }
+}
+
+impl WindowCommand {
+    fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        let window = caps.window;
+        let (n, title) = match self {
+            WindowCommand::SetTitle(t) if window.set_title => (2, t),
+            WindowCommand::SetIconName(t) if window.set_icon_name => (1, t),
+            WindowCommand::SetTitleAndIconName(t) if window.set_title && window.set_icon_name => {
+                (0, t)
+            }
+            WindowCommand::SetTitleAndIconName(t) if window.set_title => (2, t),
+            WindowCommand::PushTitle if window.title_stack => {
+                write!(out, "\x1b[22;0t")?;
+                return Ok(());
+            }
+            WindowCommand::PopTitle if window.title_stack => {
+                write!(out, "\x1b[23;0t")?;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        let sanitized: String = title.chars().filter(|c| !c.is_control()).collect();
+        write!(out, "\x1b]{n};{sanitized}\x1b\\")?;
+        Ok(())
+    }
+}
+
+impl ClipboardCommand {
+    fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        match self {
+            ClipboardCommand::Copy { selection, text } => {
+                if !caps.clipboard.copy {
+                    return Err(WriteCommandError::Unsupported("setting the clipboard"));
+                }
+
+                let encoded = clipboard::base64_encode(text.as_bytes());
+                match caps.clipboard.max_payload {
+                    Some(max) if encoded.len() > max => {
+                        return Err(WriteCommandError::PayloadTooLarge { size: encoded.len(), max })
+                    }
+                    _ => clipboard::write_copy(out, *selection, &encoded)?,
+                }
+            }
+            ClipboardCommand::Query { selection } => {
+                if !caps.clipboard.query {
+                    return Err(WriteCommandError::Unsupported("requesting the clipboard"));
+                }
+
+                clipboard::write_query(out, *selection)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PaletteCommand {
+    fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        let palette = caps.palette;
+        let (supported, name) = match self {
+            PaletteCommand::Set { entry: PaletteEntry::Indexed(_), .. }
+            | PaletteCommand::Reset { entry: PaletteEntry::Indexed(_) }
+            | PaletteCommand::ResetAllIndexed => (palette.set_palette, "setting the palette"),
+            PaletteCommand::Query { entry: PaletteEntry::Indexed(_) } => {
+                (palette.query_palette, "requesting the palette")
+            }
+            PaletteCommand::Set { .. } | PaletteCommand::Reset { .. } => {
+                (palette.set_dynamic, "setting the default colors")
+            }
+            PaletteCommand::Query { .. } => {
+                (palette.query_dynamic, "requesting the default colors")
+            }
+        };
+
+        if !supported {
+            return Err(WriteCommandError::Unsupported(name));
+        }
+
+        match *self {
+            PaletteCommand::Set { entry, color } => entry.write_set(out, color)?,
+            PaletteCommand::Query { entry } => entry.write_query(out)?,
+            PaletteCommand::Reset { entry } => entry.write_reset(out)?,
+            PaletteCommand::ResetAllIndexed => write!(out, "\x1b]104\x1b\\")?,
+        }
+
+        Ok(())
+    }
+}
+
+impl KeyboardCommand {
+    fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        if !caps.keyboard.kitty_protocol {
+            return Err(WriteCommandError::Unsupported(
+                "the kitty keyboard protocol",
+            ));
+        }
+
+        match *self {
+            KeyboardCommand::PushFlags(flags) => write!(out, "\x1b[>{}u", flags.bits())?,
+            KeyboardCommand::PopFlags(0) => (),
+            KeyboardCommand::PopFlags(1) => out.write_all(b"\x1b[<u")?,
+            KeyboardCommand::PopFlags(n) => write!(out, "\x1b[<{n}u")?,
+            KeyboardCommand::QueryFlags => out.write_all(b"\x1b[?u")?,
+        }
+
+        Ok(())
+    }
+}
+
+impl EraseCommand {
+    fn write_to<W: io::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        let (supported, name) = match self {
+            EraseCommand::ToLineEnd | EraseCommand::ToLineStart | EraseCommand::Line => {
+                (caps.erase.in_line, "erasing in the line")
+            }
+            EraseCommand::Below | EraseCommand::Above | EraseCommand::Screen => {
+                (caps.erase.in_display, "erasing in the display")
+            }
+            EraseCommand::Scrollback => (caps.erase.scrollback, "erasing the scrollback"),
+            EraseCommand::Characters { .. } => (caps.erase.characters, "erasing characters"),
+        };
+
+        if !supported {
+            return Err(WriteCommandError::Unsupported(name));
+        }
+
+        match *self {
+            EraseCommand::ToLineEnd => write!(out, "\x1b[K")?,
+            EraseCommand::ToLineStart => write!(out, "\x1b[1K")?,
+            EraseCommand::Line => write!(out, "\x1b[2K")?,
+            EraseCommand::Below => write!(out, "\x1b[J")?,
+            EraseCommand::Above => write!(out, "\x1b[1J")?,
+            EraseCommand::Screen => write!(out, "\x1b[2J")?,
+            EraseCommand::Scrollback => write!(out, "\x1b[3J")?,
+            EraseCommand::Characters { count: 0 } => (),
+            EraseCommand::Characters { count } => write_csi_count(out, count as u32, 'X')?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes `ESC[<N>S` or `ESC[<N>T` to scroll the contents up or down
+fn write_scroll<W: io::Write + ?Sized>(out: &mut W, amount: i16) -> io::Result<()> {
+    match amount {
+        0 => Ok(()),
+        1.. => write_csi_count(out, amount.unsigned_abs() as u32, 'S'),
+        _ => write_csi_count(out, amount.unsigned_abs() as u32, 'T'),
+    }
+}
+
+/// Writes a CSI sequence with a single numeric parameter, omitting the parameter if it's equal to
+/// the default of 1
+fn write_csi_count<W: io::Write + ?Sized>(out: &mut W, n: u32, finalizer: char) -> io::Result<()> {
+    match n {
+        1 => write!(out, "\x1b[{finalizer}"),
+        _ => write!(out, "\x1b[{n}{finalizer}"),
+    }
+}
+
+#[cfg(test)]
+fn encode(cmd: Command, terminal: &str) -> String {
+    let mut out = Vec::new();
+    cmd.write_to(&mut out, &crate::capinfo::test_caps(terminal))
+        .unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[cfg(test)]
+#[test]
+fn test_cursor_movement() {
+    let move_to = |x, y| Command::Cursor(CursorCommand::MoveTo { x, y });
+    let move_rel = |x, y| Command::Cursor(CursorCommand::MoveRelative { x, y });
+
+    assert_eq!(encode(move_to(Some(0), Some(0)), "xterm"), "\x1b[H");
+    assert_eq!(encode(move_to(Some(4), Some(2)), "xterm"), "\x1b[3;5H");
+    assert_eq!(encode(move_to(Some(4), None), "xterm"), "\x1b[5G");
+    assert_eq!(encode(move_to(None, Some(0)), "xterm"), "\x1b[d");
+    assert_eq!(encode(move_rel(0, -3), "xterm"), "\x1b[3A");
+    assert_eq!(encode(move_rel(1, 2), "xterm"), "\x1b[C\x1b[2B");
+}
+
+#[cfg(test)]
+#[test]
+fn test_cursor_shape() {
+    let shape = |shape, blink| Command::Cursor(CursorCommand::SetShape { shape, blink });
+
+    assert_eq!(encode(shape(CursorShape::Block, true), "xterm"), "\x1b[1 q");
+    assert_eq!(encode(shape(CursorShape::Bar, false), "xterm"), "\x1b[6 q");
+    assert_eq!(
+        encode(Command::Cursor(CursorCommand::Hide), "xterm"),
+        "\x1b[?25l"
+    );
+
+    let mut caps = crate::capinfo::test_caps("xterm");
+    caps.cursor.set_style.xterm_extended = false;
+    let mut out = Vec::new();
+    shape(CursorShape::Bar, true)
+        .write_to(&mut out, &caps)
+        .unwrap();
+    assert_eq!(out, b"\x1b[3 q");
+}
+
+#[cfg(test)]
+#[test]
+fn test_erase() {
+    let erase = |cmd| encode(Command::Erase(cmd), "xterm");
+
+    assert_eq!(erase(EraseCommand::ToLineEnd), "\x1b[K");
+    assert_eq!(erase(EraseCommand::Screen), "\x1b[2J");
+    assert_eq!(erase(EraseCommand::Characters { count: 1 }), "\x1b[X");
+    assert_eq!(erase(EraseCommand::Characters { count: 12 }), "\x1b[12X");
+
+    let mut out = Vec::new();
+    let caps = crate::capinfo::test_caps("urxvt");
+    let result = Command::Erase(EraseCommand::Scrollback).write_to(&mut out, &caps);
+    assert!(matches!(result, Err(WriteCommandError::Unsupported(_))));
+}
+
+#[cfg(test)]
+#[test]
+fn test_edit() {
+    use crate::vt::VirtualScreen;
+
+    let edit = |cmd| encode(Command::Edit(cmd), "xterm");
+    assert_eq!(
+        edit(EditCommand::InsertLines { y: 2, count: 1 }),
+        "\x1b[3;1H\x1b[L"
+    );
+    assert_eq!(
+        edit(EditCommand::DeleteLines { y: 0, count: 3 }),
+        "\x1b[H\x1b[3M"
+    );
+    assert_eq!(edit(EditCommand::InsertCharacters { count: 2 }), "\x1b[2@");
+    assert_eq!(edit(EditCommand::DeleteCharacters { count: 1 }), "\x1b[P");
+
+    // Each of inserting lines and scroll regions can be emulated with the other
+    let caps = crate::capinfo::test_caps("xterm");
+    let mut no_edit = caps;
+    no_edit.edit.lines = false;
+    let mut no_region = caps;
+    no_region.scroll.set_region = false;
+
+    let commands = [
+        Command::Edit(EditCommand::InsertLines { y: 1, count: 2 }),
+        Command::Edit(EditCommand::DeleteLines { y: 2, count: 1 }),
+        Command::Scroll(ScrollCommand::Bounded { y_range: 1..4, amount: 1 }),
+        Command::Scroll(ScrollCommand::Bounded { y_range: 0..3, amount: -2 }),
+    ];
+    for cmd in commands {
+        let mut screens = [&caps, &no_edit, &no_region].map(|caps| {
+            let mut screen = VirtualScreen::new(3, 5);
+            screen.feed(b"a\r\nb\r\nc\r\nd\r\ne");
+            cmd.write_to(&mut screen, caps).unwrap();
+            screen
+        });
+        let expected = screens[0].text();
+        for screen in &mut screens {
+            assert_eq!(screen.text(), expected, "{cmd:?}");
+        }
+    }
+    let mut out = Vec::new();
+    let cmd = Command::Scroll(ScrollCommand::Bounded { y_range: 1..4, amount: 1 });
+    cmd.write_to(&mut out, &no_region).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\x1b[2;1H\x1b[M\x1b[4;1H\x1b[L\x1b[H"
+    );
+
+    let mut out = Vec::new();
+    no_edit.edit.chars = false;
+    let result =
+        Command::Edit(EditCommand::InsertCharacters { count: 1 }).write_to(&mut out, &no_edit);
+    assert!(matches!(result, Err(WriteCommandError::Unsupported(_))));
+}
+
+#[cfg(test)]
+#[test]
+fn test_set_mode() {
+    let set_mode = |mode, enabled| Command::SetMode { mode, enabled };
+
+    assert_eq!(
+        encode(set_mode(Mode::AlternateScreen, true), "xterm"),
+        "\x1b[?1049h"
+    );
+    assert_eq!(
+        encode(set_mode(Mode::BracketedPaste, false), "xterm"),
+        "\x1b[?2004l"
+    );
+    assert_eq!(
+        encode(Command::QueryMode(Mode::SynchronizedOutput), "urxvt"),
+        "\x1b[?2026$p"
+    );
+
+    let mut out = Vec::new();
+    let caps = crate::capinfo::test_caps("urxvt");
+    let result = set_mode(Mode::FocusEvents, true).write_to(&mut out, &caps);
+    assert!(matches!(result, Err(WriteCommandError::Unsupported(_))));
+}
+
+#[cfg(test)]
+#[test]
+fn test_set_style() {
+    use crate::{Color, UnderlineShape, UnderlineStyle};
+
+    let curly = UnderlineStyle {
+        color: Some(Color::Rgb(1, 2, 3)),
+        style: UnderlineShape::Curly,
+    };
+    let style = Style::new()
+        .bold(true)
+        .foreground(Some(Color::Fixed(9)))
+        .underline(Some(curly));
+
+    assert_eq!(
+        encode(Command::SetStyle(style), "kitty"),
+        "\x1b[0;1;4:3;58;2;1;2;3;91m"
+    );
+    assert_eq!(encode(Command::SetStyle(style), "urxvt"), "\x1b[0;1;4;91m");
+
+    let double = UnderlineStyle { color: None, style: UnderlineShape::Double };
+    let style = Style::new()
+        .underline(Some(double))
+        .background(Some(Color::Fixed(200)));
+    assert_eq!(
+        encode(Command::SetStyle(style), "xterm"),
+        "\x1b[0;21;48;5;200m"
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_window() {
+    let window = |cmd, terminal| encode(Command::Window(cmd), terminal);
+
+    let title = "foo.rs\x1b]0;evil\x07 - dymium".to_owned();
+    let set_both = WindowCommand::SetTitleAndIconName(title);
+    assert_eq!(
+        window(set_both.clone(), "xterm"),
+        "\x1b]0;foo.rs]0;evil - dymium\x1b\\"
+    );
+    assert_eq!(
+        window(set_both, "kitty"),
+        "\x1b]2;foo.rs]0;evil - dymium\x1b\\"
+    );
+
+    assert_eq!(window(WindowCommand::PushTitle, "xterm"), "\x1b[22;0t");
+    assert_eq!(window(WindowCommand::PopTitle, "urxvt"), "");
+}
+
+#[cfg(test)]
+#[test]
+fn test_clipboard() {
+    let copy = |text: &str| {
+        let text = text.to_owned();
+        Command::Clipboard(ClipboardCommand::Copy {
+            selection: ClipboardSelection::Clipboard,
+            text,
+        })
+    };
+
+    assert_eq!(encode(copy("hello"), "kitty"), "\x1b]52;c;aGVsbG8=\x1b\\");
+
+    let mut caps = crate::capinfo::test_caps("kitty");
+    caps.clipboard.max_payload = Some(4);
+    let result = copy("hello").write_to(&mut Vec::new(), &caps);
+    assert!(matches!(
+        result,
+        Err(WriteCommandError::PayloadTooLarge { size: 8, max: 4 })
+    ));
+}
+
+#[cfg(test)]
+#[test]
+fn test_palette() {
+    use crate::Color;
+
+    let palette = |cmd| encode(Command::Palette(cmd), "xterm");
+
+    let entry = PaletteEntry::Indexed(3);
+    let set = PaletteCommand::Set { entry, color: Color::Rgb(0xfa, 0x82, 0x14) };
+    assert_eq!(palette(set), "\x1b]4;3;rgb:fa/82/14\x1b\\");
+    assert_eq!(palette(PaletteCommand::Reset { entry }), "\x1b]104;3\x1b\\");
+
+    let entry = PaletteEntry::Background;
+    assert_eq!(palette(PaletteCommand::Query { entry }), "\x1b]11;?\x1b\\");
+    assert_eq!(palette(PaletteCommand::Reset { entry }), "\x1b]111\x1b\\");
+}
+
+#[cfg(test)]
+#[test]
+fn test_keyboard() {
+    let keyboard = |cmd| encode(Command::Keyboard(cmd), "kitty");
+
+    let flags = KeyboardFlags::DISAMBIGUATE_ESCAPE_CODES | KeyboardFlags::REPORT_ALTERNATE_KEYS;
+    assert_eq!(keyboard(KeyboardCommand::PushFlags(flags)), "\x1b[>5u");
+    assert_eq!(keyboard(KeyboardCommand::PopFlags(1)), "\x1b[<u");
+    assert_eq!(keyboard(KeyboardCommand::PopFlags(3)), "\x1b[<3u");
+    assert_eq!(keyboard(KeyboardCommand::QueryFlags), "\x1b[?u");
+
+    let mut out = Vec::new();
+    let caps = crate::capinfo::test_caps("xterm");
+    let result = Command::Keyboard(KeyboardCommand::QueryFlags).write_to(&mut out, &caps);
+    assert!(matches!(result, Err(WriteCommandError::Unsupported(_))));
+}
+
+#[cfg(test)]
+#[test]
+fn test_set_hyperlink() {
+    let link = Hyperlink::new("file:///tmp/my file.rs").id("a:b;c\x07");
+    let open = Command::SetHyperlink(Some(link));
+
+    assert_eq!(
+        encode(open.clone(), "kitty"),
+        "\x1b]8;id=abc;file:///tmp/my%20file.rs\x1b\\"
+    );
+    assert_eq!(
+        encode(Command::SetHyperlink(None), "kitty"),
+        "\x1b]8;;\x1b\\"
+    );
+    assert_eq!(encode(open, "xterm"), "");
+
+    let link = Hyperlink::new("https://example.com/caf\u{e9}");
+    let open = Command::SetHyperlink(Some(link));
+    assert_eq!(
+        encode(open, "kitty"),
+        "\x1b]8;;https://example.com/caf%C3%A9\x1b\\"
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_change_style() {
+    use crate::Color;
+
+    let change = |from, to| Command::ChangeStyle { from, to };
+    let base = Style::new().bold(true).foreground(Some(Color::Fixed(1)));
+
+    assert_eq!(encode(change(base, base), "kitty"), "");
+    assert_eq!(encode(change(base, base.italic(true)), "kitty"), "\x1b[3m");
+    assert_eq!(encode(change(base, base.bold(false)), "kitty"), "\x1b[22m");
+    assert_eq!(
+        encode(change(base, base.foreground(None)), "kitty"),
+        "\x1b[39m"
+    );
+
+    // VTE can't unset italics, so we have to reset everything
+    let italic = base.italic(true);
+    assert_eq!(encode(change(italic, base), "libvte"), "\x1b[0;1;31m");
+    // ... unless the difference is unsupported anyways
+    assert_eq!(encode(change(base, base.faint(true)), "libvte"), "");
+
+    let struck = base.strikethrough(true);
+    assert_eq!(encode(change(struck, base), "xterm"), "\x1b[29m");
+    assert_eq!(encode(change(struck, base), "libvte"), "\x1b[29m");
+}
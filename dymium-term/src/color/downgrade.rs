@@ -0,0 +1,123 @@
+//! Mapping colors to the nearest one that a terminal can display
+//!
+//! Nearest colors are determined by euclidean distance in the [OKLab] color space, which is a
+//! reasonable approximation of perceptual difference -- unlike distance between RGB values, which
+//! overweights differences in blue and underweights differences in green.
+//!
+//! For the colors in the 16-color palette, we assume the default values used by Xterm. Terminals
+//! (and users!) will often change these, so the result is only ever approximate.
+//!
+//! [OKLab]: https://bottosson.github.io/posts/oklab/
+
+use std::sync::OnceLock;
+
+/// Default RGB values for the 16-color palette, as used by Xterm
+static XTERM_16_COLORS: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// Channel values used by each step of the 6x6x6 color cube in 8-bit colors
+static CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Returns the (approximate) RGB value of the 8-bit color
+pub(super) fn fixed_to_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => XTERM_16_COLORS[n as usize],
+        16..=231 => {
+            let i = n - 16;
+            let level = |x: u8| CUBE_LEVELS[x as usize];
+            (level(i / 36), level((i / 6) % 6), level(i % 6))
+        }
+        232..=255 => {
+            let v = 8 + 10 * (n - 232);
+            (v, v, v)
+        }
+    }
+}
+
+/// Returns the 8-bit color in `range` that's perceptually closest to the RGB value
+pub(super) fn nearest_fixed(rgb: (u8, u8, u8), range: std::ops::RangeInclusive<u8>) -> u8 {
+    static PALETTE: OnceLock<[OkLab; 256]> = OnceLock::new();
+
+    let palette = PALETTE.get_or_init(|| {
+        let mut arr = [OkLab::default(); 256];
+        for (i, lab) in arr.iter_mut().enumerate() {
+            *lab = OkLab::from_rgb(fixed_to_rgb(i as u8));
+        }
+        arr
+    });
+
+    let target = OkLab::from_rgb(rgb);
+    range
+        .min_by(|&x, &y| {
+            let dx = target.distance_squared(&palette[x as usize]);
+            let dy = target.distance_squared(&palette[y as usize]);
+            dx.total_cmp(&dy)
+        })
+        .expect("range must not be empty")
+}
+
+/// A color in the OKLab color space
+#[derive(Debug, Copy, Clone, Default)]
+struct OkLab {
+    l: f64,
+    a: f64,
+    b: f64,
+}
+
+impl OkLab {
+    fn from_rgb((r, g, b): (u8, u8, u8)) -> Self {
+        // sRGB -> linear RGB
+        let linear = |c: u8| {
+            let c = c as f64 / 255.0;
+            match c <= 0.04045 {
+                true => c / 12.92,
+                false => ((c + 0.055) / 1.055).powf(2.4),
+            }
+        };
+        let (r, g, b) = (linear(r), linear(g), linear(b));
+
+        // linear RGB -> LMS -> OKLab, with the matrices from the reference implementation
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+        OkLab {
+            l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        }
+    }
+
+    fn distance_squared(&self, other: &Self) -> f64 {
+        let (dl, da, db) = (self.l - other.l, self.a - other.a, self.b - other.b);
+        dl * dl + da * da + db * db
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_nearest_fixed() {
+    // Exact matches should always be found
+    assert_eq!(nearest_fixed((95, 135, 255), 16..=255), 16 + 36 + 2 * 6 + 5);
+    assert_eq!(nearest_fixed((128, 128, 128), 16..=255), 244);
+    assert_eq!(nearest_fixed((0xcd, 0, 0), 0..=15), 1);
+
+    // Something that's nearly orange shouldn't end up as yellow or red
+    assert_eq!(nearest_fixed((250, 130, 20), 16..=255), 208);
+}
@@ -3,10 +3,14 @@
 //! This module is extracted out from [`style`](crate::style) because it is complex enough to
 //! warrant a separate place to gather all that complexity together.
 
+use crate::capinfo::ColorCap;
+use crate::style::SgrParams;
+use std::io;
 use std::str::FromStr;
 use thiserror::Error;
 
 mod css_names;
+mod downgrade;
 mod vim_names;
 
 /// Representation of a color that can be displayed in the terminal
@@ -18,7 +22,7 @@ mod vim_names;
 /// [`green`]: Self::green
 /// [`fixed`]: Self::fixed
 /// [`rgb`]: Self::rgb
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Color {
     /// A 3-4-bit or 8-bit color
     ///
@@ -28,7 +32,7 @@ pub enum Color {
     ///
     /// ## Output
     ///
-    /// The rest of the 256 colors are emitted as `ESC[38:5:<N>m` (foreground) and `ESC[48:5:<N>m`
+    /// The rest of the 256 colors are emitted as `ESC[38;5;<N>m` (foreground) and `ESC[48;5;<N>m`
     /// (background), while the first 16 are `ESC[<N: 30-37, 90-97>m` (foreground) and
     /// `ESC[<N: 40-47, 100-107>m`(background)
     ///
@@ -45,7 +49,8 @@ pub enum Color {
     /// ## Output
     ///
     /// These colors are emitted as `ESC[38;2;<R>;<G>;<B>m` (foreground) and
-    /// `ESC[48;2;<R>;<G>;<B>m` (background).
+    /// `ESC[48;2;<R>;<G>;<B>m` (background), unless the terminal only supports the Xterm-style
+    /// `ESC[38:2::<R>:<G>:<B>m` (see [`RgbCapSet`](crate::capinfo::RgbCapSet)).
     ///
     /// ## Parsing
     ///
@@ -76,6 +81,146 @@ impl Color {
     }
 }
 
+/// The part of the text that a [`Color`] is applied to
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum ColorLayer {
+    Foreground,
+    Background,
+    Underline,
+}
+
+impl Color {
+    /// Returns the closest color that the terminal can display, or `None` if it can't display
+    /// colors at all
+    ///
+    /// Colors that are already supported are returned unchanged. Otherwise, RGB colors are mapped
+    /// to the 6x6x6 color cube or grayscale ramp of the 8-bit colors, and 8-bit colors are mapped
+    /// to the 16 named colors. The nearest color is chosen by perceptual difference (using the
+    /// [OKLab] color space), assuming Xterm's default palette for the 16 named colors.
+    ///
+    /// [OKLab]: https://bottosson.github.io/posts/oklab/
+    pub fn downgrade(self, cap: ColorCap) -> Option<Color> {
+        let rgb_supported = match cap {
+            ColorCap::None => return None,
+            ColorCap::Fixed4Bit | ColorCap::Fixed8Bit => false,
+            ColorCap::Rgb(rgb) => rgb.konsole || rgb.xterm,
+        };
+
+        let only_4bit = matches!(cap, ColorCap::Fixed4Bit);
+        match self {
+            Color::Fixed(0..=15) => Some(self),
+            Color::Fixed(n) if only_4bit => {
+                let rgb = downgrade::fixed_to_rgb(n);
+                Some(Color::Fixed(downgrade::nearest_fixed(rgb, 0..=15)))
+            }
+            Color::Fixed(_) => Some(self),
+            Color::Rgb(..) if rgb_supported => Some(self),
+            Color::Rgb(r, g, b) => {
+                let range = if only_4bit { 0..=15 } else { 16..=255 };
+                Some(Color::Fixed(downgrade::nearest_fixed((r, g, b), range)))
+            }
+        }
+    }
+
+    /// Returns the RGB value of the color
+    ///
+    /// For [`Color::Fixed`], this is only an approximation, because the terminal's palette is
+    /// unknown: we assume Xterm's default values for the 16 named colors.
+    pub(crate) fn approx_rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Fixed(n) => downgrade::fixed_to_rgb(n),
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    /// Writes the color as an X11 color specification (`rgb:<RR>/<GG>/<BB>`), as used by OSC
+    /// color sequences
+    pub(crate) fn write_x11_spec<W: io::Write + ?Sized>(self, out: &mut W) -> io::Result<()> {
+        let (r, g, b) = self.approx_rgb();
+        write!(out, "rgb:{r:02x}/{g:02x}/{b:02x}")
+    }
+
+    /// Parses an X11 color specification, as sent by terminals in reply to OSC color queries
+    ///
+    /// Both the `rgb:<R>/<G>/<B>` form (with 1 to 4 hex digits per channel) and the older
+    /// `#<RGB>` form (with 1 to 4 hex digits per channel, all the same length) are accepted.
+    pub(crate) fn parse_x11_spec(spec: &[u8]) -> Option<Color> {
+        fn channel(digits: &[u8], scale: bool) -> Option<u8> {
+            if digits.is_empty() || digits.len() > 4 {
+                return None;
+            }
+
+            let hex = |d: &u8| (*d as char).to_digit(16);
+            let value = digits.iter().try_fold(0, |v, d| Some(v * 16 + hex(d)?))?;
+            let bits = 4 * digits.len() as u32;
+            match scale {
+                // `rgb:` values are scaled to the full range: e.g., `f` and `ffff` are both 255
+                true => Some(((value * 255 + (1 << bits) / 2) / ((1 << bits) - 1)) as u8),
+                // `#` values are just the most significant bits
+                false if bits >= 8 => Some((value >> (bits - 8)) as u8),
+                false => Some((value << (8 - bits)) as u8),
+            }
+        }
+
+        if let Some(rest) = spec.strip_prefix(b"rgb:") {
+            let mut parts = rest.split(|&b| b == b'/');
+            let mut next = || channel(parts.next()?, true);
+            let color = Color::Rgb(next()?, next()?, next()?);
+            match parts.next() {
+                Some(_) => None,
+                None => Some(color),
+            }
+        } else if let Some(rest) = spec.strip_prefix(b"#") {
+            let len = rest.len() / 3;
+            if len == 0 || rest.len() % 3 != 0 {
+                return None;
+            }
+
+            let mut channels = rest.chunks(len).map(|c| channel(c, false));
+            let mut next = || channels.next().flatten();
+            Some(Color::Rgb(next()?, next()?, next()?))
+        } else {
+            None
+        }
+    }
+
+    /// Adds the SGR parameters that set this color for the given layer
+    ///
+    /// The color is first [downgraded](Self::downgrade) to what the terminal can display. If the
+    /// terminal can't display colors, nothing is added.
+    pub(crate) fn push_sgr_params(self, layer: ColorLayer, cap: ColorCap, params: &mut SgrParams) {
+        let extended = match layer {
+            ColorLayer::Foreground => 38,
+            ColorLayer::Background => 48,
+            ColorLayer::Underline => 58,
+        };
+
+        let color = match self.downgrade(cap) {
+            Some(c) => c,
+            None => return,
+        };
+
+        match (color, cap) {
+            // The first 16 colors have their own dedicated parameters for foreground and
+            // background, but underlines only have the extended form
+            (Color::Fixed(n @ 0..=15), _) if !matches!(layer, ColorLayer::Underline) => {
+                let base = match (layer, n < 8) {
+                    (ColorLayer::Foreground, true) => 30,
+                    (ColorLayer::Foreground, false) => 90 - 8,
+                    (_, true) => 40,
+                    (_, false) => 100 - 8,
+                };
+                params.push(base + n as u16);
+            }
+            (Color::Fixed(n), _) => params.push(format_args!("{extended};5;{n}")),
+            (Color::Rgb(r, g, b), ColorCap::Rgb(rgb)) if rgb.konsole => {
+                params.push(format_args!("{extended};2;{r};{g};{b}"))
+            }
+            (Color::Rgb(r, g, b), _) => params.push(format_args!("{extended}:2::{r}:{g}:{b}")),
+        }
+    }
+}
+
 macro_rules! named_color_methods {
     (
         $(
@@ -192,7 +337,7 @@ impl FromStr for Color {
             let hexdigit = |idx: usize| -> u8 {
                 match s.as_bytes()[idx] {
                     b @ b'0'..=b'9' => b - b'0',
-                    b @ b'a'..=b'f' => b - b'a',
+                    b @ b'a'..=b'f' => b - b'a' + 10,
                     _ => unreachable!(),
                 }
             };
@@ -261,3 +406,47 @@ impl FromStr for Color {
         }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test_downgrade() {
+    use crate::capinfo::RgbCapSet;
+
+    let orange: Color = "#fa8214".parse().unwrap();
+    assert_eq!(orange, Color::Rgb(0xfa, 0x82, 0x14));
+
+    let no_rgb = ColorCap::Rgb(RgbCapSet { xterm: false, konsole: false });
+    assert_eq!(
+        orange.downgrade(ColorCap::Fixed8Bit),
+        Some(Color::Fixed(208))
+    );
+    assert_eq!(orange.downgrade(no_rgb), Some(Color::Fixed(208)));
+    assert_eq!(orange.downgrade(ColorCap::Fixed4Bit), Some(Color::Fixed(9)));
+    assert_eq!(orange.downgrade(ColorCap::None), None);
+
+    assert_eq!(
+        Color::Fixed(196).downgrade(ColorCap::Fixed4Bit),
+        Some(Color::Fixed(9))
+    );
+    assert_eq!(
+        Color::Fixed(196).downgrade(ColorCap::Fixed8Bit),
+        Some(Color::Fixed(196))
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_x11_spec() {
+    let parse = |s: &str| Color::parse_x11_spec(s.as_bytes());
+
+    assert_eq!(parse("rgb:ffff/8080/0000"), Some(Color::Rgb(255, 128, 0)));
+    assert_eq!(parse("rgb:f/8/0"), Some(Color::Rgb(255, 136, 0)));
+    assert_eq!(parse("rgb:ff/80/00"), Some(Color::Rgb(255, 128, 0)));
+    assert_eq!(parse("#ff8000"), Some(Color::Rgb(255, 128, 0)));
+    assert_eq!(parse("#ffff80800000"), Some(Color::Rgb(255, 128, 0)));
+    assert_eq!(parse("rgb:ff/80"), None);
+    assert_eq!(parse("rgb:ff/80/00/00"), None);
+    assert_eq!(parse("rgb:fffff/0/0"), None);
+    assert_eq!(parse("#"), None);
+    assert_eq!(parse("#ff80"), None);
+}
@@ -0,0 +1,65 @@
+//! Hyperlinks attached to text, via OSC 8
+
+use std::io;
+
+/// A hyperlink that can be attached to text
+///
+/// Hyperlinks are applied with [`Command::SetHyperlink`](crate::Command::SetHyperlink): all text
+/// written while the hyperlink is set becomes part of it, until it is unset again. Terminals that
+/// don't support hyperlinks (see [`StyleCap::hyperlinks`]) just display the text as normal.
+///
+/// [`StyleCap::hyperlinks`]: crate::capinfo::StyleCap::hyperlinks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hyperlink {
+    /// The URI that the hyperlink points to, e.g. `file://host/path/to/file`
+    ///
+    /// Spaces and any characters outside of printable ASCII are percent-encoded on output.
+    pub uri: String,
+    /// An identifier for the hyperlink, if provided
+    ///
+    /// Separate pieces of text with the same `id` and `uri` are treated by the terminal as a
+    /// single link -- e.g., when a link is split across multiple lines by the editor. Without an
+    /// `id`, only adjacent text is grouped together.
+    ///
+    /// Characters that would end the parameter (`:` and `;`) or the escape sequence (control
+    /// characters) are removed on output.
+    pub id: Option<String>,
+}
+
+impl Hyperlink {
+    /// Creates a new `Hyperlink` to the URI, without an id
+    pub fn new(uri: impl Into<String>) -> Self {
+        Hyperlink { uri: uri.into(), id: None }
+    }
+
+    /// Sets the id of the hyperlink
+    pub fn id(self, id: impl Into<String>) -> Self {
+        Hyperlink { id: Some(id.into()), ..self }
+    }
+
+    /// Writes the escape sequence that opens the hyperlink
+    pub(crate) fn write_open<W: io::Write + ?Sized>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(b"\x1b]8;")?;
+        if let Some(id) = &self.id {
+            let id = id
+                .chars()
+                .filter(|&c| !c.is_control() && c != ':' && c != ';');
+            write!(out, "id={}", id.collect::<String>())?;
+        }
+        out.write_all(b";")?;
+
+        for &b in self.uri.as_bytes() {
+            match b {
+                b'!'..=b'~' => out.write_all(&[b])?,
+                _ => write!(out, "%{b:02X}")?,
+            }
+        }
+
+        out.write_all(b"\x1b\\")
+    }
+
+    /// Writes the escape sequence that closes any open hyperlink
+    pub(crate) fn write_close<W: io::Write + ?Sized>(out: &mut W) -> io::Result<()> {
+        out.write_all(b"\x1b]8;;\x1b\\")
+    }
+}
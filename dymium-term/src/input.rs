@@ -0,0 +1,965 @@
+//! Parsing input from the terminal into [`Event`]s
+//!
+//! The input from a terminal is a single stream of bytes mixing key presses, mouse reports, pasted
+//! text, and replies to queries. [`InputParser`] splits it back into its pieces, as it arrives.
+
+use crate::{ClipboardReply, KeyboardFlags, KeyboardReply, ModeReport, PaletteReply};
+use std::fmt::{self, Debug, Formatter};
+use std::ops::{BitOr, BitOrAssign};
+
+const ESC: u8 = 0x1b;
+
+/// Incremental parser for input from the terminal
+///
+/// Bytes are given to the parser with [`feed`] as they're read from the tty, and events are taken
+/// out of it with [`next_event`]. Input doesn't need to be split at any particular boundary: if an
+/// escape sequence (or UTF-8 character) is split across reads, the parser waits for the rest of
+/// it.
+///
+/// ## Pending escapes
+///
+/// Without the [Kitty keyboard protocol], pressing `Esc` sends a lone `ESC` byte, which is also how
+/// every escape sequence starts. So when the input ends with `ESC`, there's no way to tell whether
+/// it was the `Esc` key or the start of a sequence that hasn't fully arrived yet. The parser
+/// doesn't guess: once [`next_event`] returns `None`, [`has_pending_escape`] reports whether this
+/// is the case, and the caller should wait a short time (typically 10-50ms) for more input. If
+/// none arrives, [`flush`] interprets the pending input as keys.
+///
+/// The same goes for longer input. `Alt-]` and `Alt-P` are sent as `ESC` followed by the key,
+/// which is also how OSC and DCS strings start. If the next key can't start a reply from the
+/// terminal, they're parsed as keys -- but otherwise (e.g., `Alt-]` followed by `1`), all input up
+/// to the end of the string is held back until it arrives or [`flush`] is called. So callers
+/// should always fall back to `flush` after a timeout while [`has_pending_escape`] is true, rather
+/// than waiting indefinitely for more input.
+///
+/// [`feed`]: Self::feed
+/// [`next_event`]: Self::next_event
+/// [`has_pending_escape`]: Self::has_pending_escape
+/// [`flush`]: Self::flush
+/// [Kitty keyboard protocol]: crate::KeyboardFlags
+#[derive(Debug, Default)]
+pub struct InputParser {
+    buf: Vec<u8>,
+    /// True if we're inside a bracketed paste, where `buf` starts with the pasted text
+    in_paste: bool,
+    /// The length of the prefix of `buf` that's already been searched for the end of the paste
+    paste_searched: usize,
+}
+
+/// An event from the terminal, parsed by [`InputParser`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+    /// A key was pressed (or repeated, or released)
+    Key(KeyEvent),
+    /// A mouse button was pressed or released, or the mouse was moved or scrolled
+    ///
+    /// Only the SGR encoding (mode 1006) is supported.
+    Mouse(MouseEvent),
+    /// Text was pasted, with [`Mode::BracketedPaste`](crate::Mode::BracketedPaste) enabled
+    Paste(String),
+    /// The terminal gained focus, with [`Mode::FocusEvents`](crate::Mode::FocusEvents) enabled
+    FocusGained,
+    /// The terminal lost focus, with [`Mode::FocusEvents`](crate::Mode::FocusEvents) enabled
+    FocusLost,
+    /// A reply to a cursor position request (`ESC[6n`), with the top-left corner at (0, 0)
+    ///
+    /// Legacy encodings of `F3` with modifiers (e.g. `ESC[1;5R` for `Ctrl-F3`) have exactly the
+    /// same form, so they're reported as this instead. The Kitty keyboard protocol avoids this.
+    #[allow(missing_docs)]
+    CursorPosition { x: u16, y: u16 },
+    /// A reply to a request for the primary device attributes (`ESC[c`), with its parameters
+    PrimaryDeviceAttributes(Vec<u16>),
+    /// A reply to a request for the secondary device attributes (`ESC[>c`), with its parameters
+    SecondaryDeviceAttributes(Vec<u16>),
+    /// A reply to a request for the terminal's name and version (XTVERSION, `ESC[>q`)
+    TerminalVersion(String),
+    /// A reply to [`Command::QueryMode`](crate::Command::QueryMode)
+    ModeReport(ModeReport),
+    /// A reply to [`KeyboardCommand::QueryFlags`](crate::KeyboardCommand::QueryFlags)
+    KeyboardFlags(KeyboardFlags),
+    /// A reply to [`PaletteCommand::Query`](crate::PaletteCommand::Query)
+    Palette(PaletteReply),
+    /// A reply to [`ClipboardCommand::Query`](crate::ClipboardCommand::Query)
+    Clipboard(ClipboardReply),
+    /// An escape sequence that wasn't recognized, including the leading `ESC`
+    Unrecognized(Vec<u8>),
+}
+
+/// A key event, from [`Event::Key`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// The key that the event is for
+    ///
+    /// Outside of the Kitty keyboard protocol, the terminal sends the character produced by the
+    /// key, so `Shift-a` is reported as `Char('A')` without [`Modifiers::SHIFT`].
+    pub code: KeyCode,
+    /// The modifiers that were held down
+    pub modifiers: Modifiers,
+    /// The kind of event; anything other than [`KeyEventKind::Press`] is only reported with
+    /// [`KeyboardFlags::REPORT_EVENT_TYPES`]
+    pub kind: KeyEventKind,
+    /// The key that would have been produced with `Shift` held down, if known
+    ///
+    /// This is only reported with [`KeyboardFlags::REPORT_ALTERNATE_KEYS`].
+    pub shifted_key: Option<char>,
+    /// The key in the same position on a standard US (PC-101) layout, if known
+    ///
+    /// This is only reported with [`KeyboardFlags::REPORT_ALTERNATE_KEYS`].
+    pub base_layout_key: Option<char>,
+    /// The text produced by the key, if known
+    ///
+    /// This is only reported with [`KeyboardFlags::REPORT_ASSOCIATED_TEXT`].
+    pub text: Option<String>,
+}
+
+/// A key on the keyboard, from [`KeyEvent`]
+///
+/// Keys on the keypad are reported as their equivalent elsewhere on the keyboard.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+#[allow(missing_docs)]
+pub enum KeyCode {
+    /// A key that produces a character, like `a` or `Space`
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// A function key, from `F1` up to `F35`
+    F(u8),
+    CapsLock,
+    ScrollLock,
+    NumLock,
+    PrintScreen,
+    Pause,
+    Menu,
+    /// The middle key on the keypad, i.e. `5` with `NumLock` off
+    KeypadBegin,
+    /// A key from the Kitty keyboard protocol without a variant above (e.g., `Left Shift`), by its
+    /// key code
+    Other(u32),
+}
+
+/// The kind of a [`KeyEvent`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum KeyEventKind {
+    Press,
+    Repeat,
+    Release,
+}
+
+/// A set of modifier keys, from [`KeyEvent`] or [`MouseEvent`]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers(u8);
+
+/// A mouse event, from [`Event::Mouse`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// What happened
+    pub kind: MouseEventKind,
+    /// The column of the mouse, with the left edge at 0
+    pub x: u16,
+    /// The row of the mouse, with the top edge at 0
+    pub y: u16,
+    /// The modifiers that were held down; only `SHIFT`, `ALT`, and `CTRL` are reported
+    pub modifiers: Modifiers,
+}
+
+/// The kind of a [`MouseEvent`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    Release(MouseButton),
+    /// The mouse was moved while the button was held down
+    Drag(MouseButton),
+    /// The mouse was moved with no buttons held down
+    Move,
+    ScrollUp,
+    ScrollDown,
+    ScrollLeft,
+    ScrollRight,
+}
+
+/// A button on the mouse, from [`MouseEventKind`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    Back,
+    Forward,
+}
+
+/// The result of parsing a single token from the input
+enum Token {
+    Event(Event),
+    /// The start of a bracketed paste, `ESC[200~`
+    PasteStart,
+}
+
+impl InputParser {
+    /// Creates a new `InputParser` with no pending input
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the bytes to the input, to be parsed by [`next_event`](Self::next_event)
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Returns the next event from the input, if there's a complete one
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.next_inner(false)
+    }
+
+    /// Returns whether the remaining input starts with an `ESC` that could either be the `Esc` key
+    /// or the start of an incomplete escape sequence
+    ///
+    /// This is only meaningful after [`next_event`](Self::next_event) has returned `None`. See the
+    /// [type-level documentation](Self#pending-escapes) for more.
+    pub fn has_pending_escape(&self) -> bool {
+        !self.in_paste && self.buf.first() == Some(&ESC)
+    }
+
+    /// Returns the next event from the input, treating any incomplete input as if nothing else
+    /// will follow it
+    ///
+    /// This should be called repeatedly, until it returns `None`. A pending `ESC` is interpreted as
+    /// the `Esc` key -- or, if it's followed by a single byte, as `Alt` and that key. Incomplete
+    /// UTF-8 becomes `U+FFFD REPLACEMENT CHARACTER`. Bracketed pastes are never cut short.
+    pub fn flush(&mut self) -> Option<Event> {
+        self.next_inner(true)
+    }
+
+    fn next_inner(&mut self, flush: bool) -> Option<Event> {
+        if self.in_paste {
+            return self.next_paste();
+        }
+
+        let (token, len) = parse_token(&self.buf, flush)?;
+        self.buf.drain(..len);
+
+        match token {
+            Token::Event(event) => Some(event),
+            Token::PasteStart => {
+                self.in_paste = true;
+                self.paste_searched = 0;
+                self.next_paste()
+            }
+        }
+    }
+
+    fn next_paste(&mut self) -> Option<Event> {
+        const END: &[u8] = b"\x1b[201~";
+
+        // The end marker may have been split, so we have to go back a little from where we left
+        // off.
+        let start = self.paste_searched.saturating_sub(END.len() - 1);
+        let found = self.buf[start..].windows(END.len()).position(|w| w == END);
+
+        match found {
+            Some(i) => {
+                let text = String::from_utf8_lossy(&self.buf[..start + i]).into_owned();
+                self.buf.drain(..start + i + END.len());
+                self.in_paste = false;
+                Some(Event::Paste(text))
+            }
+            None => {
+                self.paste_searched = self.buf.len();
+                None
+            }
+        }
+    }
+}
+
+impl KeyEvent {
+    /// Creates a new key press event, with nothing else reported
+    pub fn new(code: KeyCode, modifiers: Modifiers) -> Self {
+        KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            shifted_key: None,
+            base_layout_key: None,
+            text: None,
+        }
+    }
+}
+
+#[allow(missing_docs)]
+impl Modifiers {
+    pub const SHIFT: Self = Modifiers(1);
+    pub const ALT: Self = Modifiers(2);
+    pub const CTRL: Self = Modifiers(4);
+    pub const SUPER: Self = Modifiers(8);
+    pub const HYPER: Self = Modifiers(16);
+    pub const META: Self = Modifiers(32);
+    pub const CAPS_LOCK: Self = Modifiers(64);
+    pub const NUM_LOCK: Self = Modifiers(128);
+
+    const ALL: [(Self, &'static str); 8] = [
+        (Self::SHIFT, "SHIFT"),
+        (Self::ALT, "ALT"),
+        (Self::CTRL, "CTRL"),
+        (Self::SUPER, "SUPER"),
+        (Self::HYPER, "HYPER"),
+        (Self::META, "META"),
+        (Self::CAPS_LOCK, "CAPS_LOCK"),
+        (Self::NUM_LOCK, "NUM_LOCK"),
+    ];
+
+    /// Returns the empty set of modifiers
+    pub const fn empty() -> Self {
+        Modifiers(0)
+    }
+
+    /// Returns the raw value of the modifiers, as a bitset in the order of the constants above
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Creates the modifiers from their raw value
+    pub const fn from_bits(bits: u8) -> Self {
+        Modifiers(bits)
+    }
+
+    /// Returns whether no modifiers are set
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns whether all of the modifiers in `other` are also set in `self`
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Modifiers(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Modifiers {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl Debug for Modifiers {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let names = Self::ALL
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name);
+
+        f.write_str("Modifiers(")?;
+        for (i, name) in names.enumerate() {
+            if i != 0 {
+                f.write_str(" | ")?;
+            }
+            f.write_str(name)?;
+        }
+        f.write_str(")")
+    }
+}
+
+fn key(code: KeyCode, modifiers: Modifiers) -> Token {
+    Token::Event(Event::Key(KeyEvent::new(code, modifiers)))
+}
+
+/// Parses the next token from the input, returning it and the number of bytes it used
+///
+/// Returns `None` if the input is incomplete (which can only happen if `flush` is false).
+fn parse_token(bytes: &[u8], flush: bool) -> Option<(Token, usize)> {
+    let (&first, rest) = bytes.split_first()?;
+    if first != ESC {
+        return parse_plain(bytes, flush);
+    }
+
+    match rest.first() {
+        None if flush => Some((key(KeyCode::Escape, Modifiers::empty()), 1)),
+        None => None,
+        // `Alt-]` and `Alt-P` look like the start of an OSC or DCS string, but the replies we
+        // parse always start with a digit (OSC) or a parameter byte (DCS), while keys are usually
+        // letters. Treating them as strings would swallow every key until a terminator arrives.
+        Some(&kind @ (b']' | b'P')) if bytes.get(2).is_some_and(|&b| !starts_string(kind, b)) => {
+            with_alt(parse_plain(rest, flush)?)
+        }
+        Some(b'[' | b'O' | b']' | b'P') => match parse_sequence(bytes) {
+            Some(parsed) => Some(parsed),
+            None if !flush => None,
+            None if bytes.len() == 2 => with_alt(parse_plain(rest, true)?),
+            None => Some((key(KeyCode::Escape, Modifiers::empty()), 1)),
+        },
+        // There's only one level of `Alt`, so in a run of `ESC`s, all but the last two are plain
+        // `Esc` keys. This also keeps the recursion below from going more than one level deep.
+        Some(&ESC) if rest.get(1) == Some(&ESC) => {
+            Some((key(KeyCode::Escape, Modifiers::empty()), 1))
+        }
+        // Terminals send `Alt-<KEY>` as `ESC` followed by the key -- including when the key itself
+        // is an escape sequence, like `ESC ESC[A` for `Alt-Up`.
+        Some(_) => with_alt(parse_token(rest, flush)?),
+    }
+}
+
+/// Returns whether `byte` can start the body of an OSC (`kind` is `]`) or DCS (`kind` is `P`)
+/// string sent by the terminal
+fn starts_string(kind: u8, byte: u8) -> bool {
+    match kind {
+        b']' => byte.is_ascii_digit(),
+        _ => (0x20..=0x3f).contains(&byte),
+    }
+}
+
+/// Adds `Alt` to the key parsed after an `ESC`, or returns just the `Esc` key if it wasn't a key
+fn with_alt((token, len): (Token, usize)) -> Option<(Token, usize)> {
+    match token {
+        Token::Event(Event::Key(mut key)) => {
+            key.modifiers |= Modifiers::ALT;
+            Some((Token::Event(Event::Key(key)), len + 1))
+        }
+        _ => Some((key(KeyCode::Escape, Modifiers::empty()), 1)),
+    }
+}
+
+/// Parses a key that isn't part of an escape sequence: either a control character or (possibly
+/// multi-byte) text
+fn parse_plain(bytes: &[u8], flush: bool) -> Option<(Token, usize)> {
+    let none = Modifiers::empty();
+    let ctrl = Modifiers::CTRL;
+
+    let token = match bytes[0] {
+        b'\r' => key(KeyCode::Enter, none),
+        b'\t' => key(KeyCode::Tab, none),
+        0x7f => key(KeyCode::Backspace, none),
+        ESC => key(KeyCode::Escape, none),
+        0 => key(KeyCode::Char(' '), ctrl),
+        b @ 0x01..=0x1a => key(KeyCode::Char((b'a' + b - 1) as char), ctrl),
+        // `Ctrl-\`, `Ctrl-]`, `Ctrl-^`, and `Ctrl-_`
+        b @ 0x1c..=0x1f => key(KeyCode::Char((b + 0x40) as char), ctrl),
+        b @ 0x20..=0x7e => key(KeyCode::Char(b as char), none),
+        _ => return parse_utf8(bytes, flush),
+    };
+
+    Some((token, 1))
+}
+
+fn parse_utf8(bytes: &[u8], flush: bool) -> Option<(Token, usize)> {
+    let invalid = |len| Some((key(KeyCode::Char('\u{FFFD}'), Modifiers::empty()), len));
+
+    let len = match bytes[0] {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return invalid(1),
+    };
+
+    if bytes.len() < len {
+        let continuing = bytes[1..].iter().all(|&b| b & 0xc0 == 0x80);
+        return match continuing {
+            true if !flush => None,
+            true => invalid(bytes.len()),
+            false => invalid(1),
+        };
+    }
+
+    match std::str::from_utf8(&bytes[..len]) {
+        Ok(s) => {
+            let c = s.chars().next()?;
+            Some((key(KeyCode::Char(c), Modifiers::empty()), len))
+        }
+        Err(_) => invalid(1),
+    }
+}
+
+/// Parses an escape sequence: CSI (`ESC[`), SS3 (`ESC O`), OSC (`ESC]`), or DCS (`ESC P`)
+///
+/// Returns `None` if the sequence is incomplete.
+fn parse_sequence(bytes: &[u8]) -> Option<(Token, usize)> {
+    let unrecognized = |len| {
+        Some((
+            Token::Event(Event::Unrecognized(bytes[..len].to_vec())),
+            len,
+        ))
+    };
+
+    match bytes[1] {
+        b'[' => {
+            let params_len = bytes[2..].iter().position(|b| !(0x30..=0x3f).contains(b))?;
+            let intermediates_len = bytes[2 + params_len..]
+                .iter()
+                .position(|b| !(0x20..=0x2f).contains(b))?;
+
+            let len = 2 + params_len + intermediates_len + 1;
+            let params = &bytes[2..2 + params_len];
+            let intermediates = &bytes[2 + params_len..len - 1];
+            match bytes[len - 1] {
+                0x40..=0x7e if intermediates.is_empty() => {
+                    match parse_csi(params, bytes[len - 1]) {
+                        Some(token) => Some((token, len)),
+                        None => unrecognized(len),
+                    }
+                }
+                b'y' if intermediates == b"$" => {
+                    let reply = params
+                        .strip_prefix(b"?")
+                        .and_then(ModeReport::parse_csi_params);
+                    match reply {
+                        Some(reply) => Some((Token::Event(Event::ModeReport(reply)), len)),
+                        None => unrecognized(len),
+                    }
+                }
+                0x40..=0x7e => unrecognized(len),
+                // Anything else is invalid in a CSI sequence, so we give up on it without
+                // consuming the invalid byte
+                _ => unrecognized(len - 1),
+            }
+        }
+        b'O' => {
+            let code = match *bytes.get(2)? {
+                b'A' => KeyCode::Up,
+                b'B' => KeyCode::Down,
+                b'C' => KeyCode::Right,
+                b'D' => KeyCode::Left,
+                b'H' => KeyCode::Home,
+                b'F' => KeyCode::End,
+                b'E' => KeyCode::KeypadBegin,
+                b'M' => KeyCode::Enter,
+                b'P' => KeyCode::F(1),
+                b'Q' => KeyCode::F(2),
+                b'R' => KeyCode::F(3),
+                b'S' => KeyCode::F(4),
+                _ => return unrecognized(3),
+            };
+            Some((key(code, Modifiers::empty()), 3))
+        }
+        // OSC and DCS: everything up to the string terminator (`ESC\`), or `BEL` for OSC
+        kind => {
+            let end = bytes[2..]
+                .iter()
+                .position(|&b| b == ESC || (b == 0x07 && kind == b']'))?
+                + 2;
+            let len = match bytes[end] {
+                0x07 => end + 1,
+                _ if *bytes.get(end + 1)? == b'\\' => end + 2,
+                // Any other escape sequence cancels the string
+                _ => end,
+            };
+
+            let body = &bytes[2..end];
+            let event = match kind {
+                b']' => PaletteReply::parse_osc_body(body)
+                    .map(Event::Palette)
+                    .or_else(|| ClipboardReply::parse_osc_body(body).map(Event::Clipboard)),
+                _ => body
+                    .strip_prefix(b">|")
+                    .map(|version| Event::TerminalVersion(String::from_utf8_lossy(version).into())),
+            };
+
+            match event {
+                Some(event) => Some((Token::Event(event), len)),
+                None => unrecognized(len),
+            }
+        }
+    }
+}
+
+/// Parameters of a CSI sequence, each with its colon-separated sub-parameters
+struct CsiParams(Vec<Vec<Option<u32>>>);
+
+impl CsiParams {
+    /// Parses the parameters, returning `None` if they're malformed (e.g., with a private marker
+    /// in the middle)
+    fn parse(params: &[u8]) -> Option<Self> {
+        if params.is_empty() {
+            return Some(CsiParams(Vec::new()));
+        }
+
+        let parse_one = |sub: &[u8]| match sub.is_empty() {
+            true => Ok(None),
+            false if sub.iter().all(u8::is_ascii_digit) => {
+                let s = std::str::from_utf8(sub).map_err(|_| ())?;
+                s.parse().map(Some).map_err(|_| ())
+            }
+            false => Err(()),
+        };
+
+        let parsed = params
+            .split(|&b| b == b';')
+            .map(|param| param.split(|&b| b == b':').map(parse_one).collect())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        Some(CsiParams(parsed))
+    }
+
+    /// Returns the `j`th sub-parameter of the `i`th parameter, if it's present
+    fn get(&self, i: usize, j: usize) -> Option<u32> {
+        *self.0.get(i)?.get(j)?
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the modifiers and event kind from the `i`th parameter, in the form
+    /// `<MODIFIERS>:<KIND>`
+    fn modifiers_and_kind(&self, i: usize) -> Option<(Modifiers, KeyEventKind)> {
+        let mods = self.get(i, 0).unwrap_or(1).checked_sub(1)?;
+        let kind = match self.get(i, 1).unwrap_or(1) {
+            1 => KeyEventKind::Press,
+            2 => KeyEventKind::Repeat,
+            3 => KeyEventKind::Release,
+            _ => return None,
+        };
+        Some((Modifiers(u8::try_from(mods).ok()?), kind))
+    }
+
+    /// Returns all of the first sub-parameters, for replies that are just a list of numbers
+    fn list(&self) -> Option<Vec<u16>> {
+        (0..self.len())
+            .map(|i| u16::try_from(self.get(i, 0).unwrap_or(0)).ok())
+            .collect()
+    }
+}
+
+/// Parses a CSI sequence without intermediate bytes, given its parameter bytes (including any
+/// private marker) and final byte
+///
+/// Returns `None` if the sequence isn't recognized.
+fn parse_csi(params: &[u8], final_byte: u8) -> Option<Token> {
+    let (private, params_bytes) = match params.first() {
+        Some(&b @ (b'<' | b'=' | b'>' | b'?')) => (Some(b), &params[1..]),
+        _ => (None, params),
+    };
+    let params = CsiParams::parse(params_bytes)?;
+
+    let key_with_params = |code, i| {
+        let (modifiers, kind) = params.modifiers_and_kind(i)?;
+        let event = KeyEvent { kind, ..KeyEvent::new(code, modifiers) };
+        Some(Token::Event(Event::Key(event)))
+    };
+
+    let event = match (private, final_byte) {
+        (None, b'A') => return key_with_params(KeyCode::Up, 1),
+        (None, b'B') => return key_with_params(KeyCode::Down, 1),
+        (None, b'C') => return key_with_params(KeyCode::Right, 1),
+        (None, b'D') => return key_with_params(KeyCode::Left, 1),
+        (None, b'H') => return key_with_params(KeyCode::Home, 1),
+        (None, b'F') => return key_with_params(KeyCode::End, 1),
+        (None, b'E') => return key_with_params(KeyCode::KeypadBegin, 1),
+        (None, b'P') => return key_with_params(KeyCode::F(1), 1),
+        (None, b'Q') => return key_with_params(KeyCode::F(2), 1),
+        (None, b'S') => return key_with_params(KeyCode::F(4), 1),
+        (None, b'R') if params.len() == 2 => {
+            let coord = |i| u16::try_from(params.get(i, 0)?.saturating_sub(1)).ok();
+            Event::CursorPosition { x: coord(1)?, y: coord(0)? }
+        }
+        (None, b'R') => return key_with_params(KeyCode::F(3), 1),
+        (None, b'Z') => {
+            let (modifiers, kind) = params.modifiers_and_kind(1)?;
+            let modifiers = modifiers | Modifiers::SHIFT;
+            Event::Key(KeyEvent { kind, ..KeyEvent::new(KeyCode::Tab, modifiers) })
+        }
+        (None, b'~') => match params.get(0, 0)? {
+            200 => return Some(Token::PasteStart),
+            n => return key_with_params(tilde_key_code(n)?, 1),
+        },
+        (None, b'u') => Event::Key(parse_kitty_key(&params)?),
+        (None, b'I') if params.len() == 0 => Event::FocusGained,
+        (None, b'O') if params.len() == 0 => Event::FocusLost,
+        (Some(b'?'), b'u') => {
+            Event::KeyboardFlags(KeyboardReply::parse_csi_params(params_bytes)?.flags)
+        }
+        (Some(b'?'), b'c') => Event::PrimaryDeviceAttributes(params.list()?),
+        (Some(b'>'), b'c') => Event::SecondaryDeviceAttributes(params.list()?),
+        (Some(b'<'), b'M' | b'm') => Event::Mouse(parse_sgr_mouse(&params, final_byte == b'm')?),
+        _ => return None,
+    };
+
+    Some(Token::Event(event))
+}
+
+/// Returns the key for sequences of the form `ESC[<N>~`
+fn tilde_key_code(n: u32) -> Option<KeyCode> {
+    let code = match n {
+        1 | 7 => KeyCode::Home,
+        2 => KeyCode::Insert,
+        3 => KeyCode::Delete,
+        4 | 8 => KeyCode::End,
+        5 => KeyCode::PageUp,
+        6 => KeyCode::PageDown,
+        11..=15 => KeyCode::F(n as u8 - 10),
+        17..=21 => KeyCode::F(n as u8 - 11),
+        23..=26 => KeyCode::F(n as u8 - 12),
+        28 | 29 => KeyCode::F(n as u8 - 13),
+        31..=34 => KeyCode::F(n as u8 - 14),
+        // The Kitty keyboard protocol may use its own key codes here (e.g. for `KeypadBegin`)
+        57344.. => kitty_key_code(n),
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Returns the key for a key code from the Kitty keyboard protocol
+fn kitty_key_code(n: u32) -> KeyCode {
+    match n {
+        9 => KeyCode::Tab,
+        13 => KeyCode::Enter,
+        27 => KeyCode::Escape,
+        127 => KeyCode::Backspace,
+        57358 => KeyCode::CapsLock,
+        57359 => KeyCode::ScrollLock,
+        57360 => KeyCode::NumLock,
+        57361 => KeyCode::PrintScreen,
+        57362 => KeyCode::Pause,
+        57363 => KeyCode::Menu,
+        57376..=57398 => KeyCode::F((n - 57376 + 13) as u8),
+        // The keypad
+        57399..=57408 => KeyCode::Char((b'0' + (n - 57399) as u8) as char),
+        57409 => KeyCode::Char('.'),
+        57410 => KeyCode::Char('/'),
+        57411 => KeyCode::Char('*'),
+        57412 => KeyCode::Char('-'),
+        57413 => KeyCode::Char('+'),
+        57414 => KeyCode::Enter,
+        57415 => KeyCode::Char('='),
+        57416 => KeyCode::Char(','),
+        57417 => KeyCode::Left,
+        57418 => KeyCode::Right,
+        57419 => KeyCode::Up,
+        57420 => KeyCode::Down,
+        57421 => KeyCode::PageUp,
+        57422 => KeyCode::PageDown,
+        57423 => KeyCode::Home,
+        57424 => KeyCode::End,
+        57425 => KeyCode::Insert,
+        57426 => KeyCode::Delete,
+        57427 => KeyCode::KeypadBegin,
+        // Anything else in the private use area is a key without a variant
+        0xe000..=0xf8ff => KeyCode::Other(n),
+        _ => char::from_u32(n).map_or(KeyCode::Other(n), KeyCode::Char),
+    }
+}
+
+/// Parses a key from the Kitty keyboard protocol, of the form
+/// `ESC[<CODE>:<SHIFTED>:<BASE>;<MODIFIERS>:<KIND>;<TEXT>u`
+fn parse_kitty_key(params: &CsiParams) -> Option<KeyEvent> {
+    let code = kitty_key_code(params.get(0, 0)?);
+    let (modifiers, kind) = params.modifiers_and_kind(1)?;
+    let alternate = |j| params.get(0, j).and_then(char::from_u32);
+
+    let text = match params.0.get(2) {
+        Some(codepoints) => Some(
+            codepoints
+                .iter()
+                .map(|c| c.and_then(char::from_u32))
+                .collect::<Option<String>>()?,
+        ),
+        None => None,
+    };
+
+    Some(KeyEvent {
+        code,
+        modifiers,
+        kind,
+        shifted_key: alternate(1),
+        base_layout_key: alternate(2),
+        text,
+    })
+}
+
+/// Parses an SGR mouse report, of the form `ESC[<<BUTTON>;<X>;<Y>M` (or `m` for releases)
+fn parse_sgr_mouse(params: &CsiParams, released: bool) -> Option<MouseEvent> {
+    if params.len() != 3 {
+        return None;
+    }
+
+    let b = params.get(0, 0)?;
+    let coord = |i| u16::try_from(params.get(i, 0)?.saturating_sub(1)).ok();
+
+    let mut modifiers = Modifiers::empty();
+    for (bit, modifier) in [
+        (4, Modifiers::SHIFT),
+        (8, Modifiers::ALT),
+        (16, Modifiers::CTRL),
+    ] {
+        if b & bit != 0 {
+            modifiers |= modifier;
+        }
+    }
+
+    let motion = b & 32 != 0;
+    let kind = match b & !(4 | 8 | 16 | 32) {
+        3 => MouseEventKind::Move,
+        64 => MouseEventKind::ScrollUp,
+        65 => MouseEventKind::ScrollDown,
+        66 => MouseEventKind::ScrollLeft,
+        67 => MouseEventKind::ScrollRight,
+        base => {
+            let button = match base {
+                0 => MouseButton::Left,
+                1 => MouseButton::Middle,
+                2 => MouseButton::Right,
+                128 => MouseButton::Back,
+                129 => MouseButton::Forward,
+                _ => return None,
+            };
+            match (released, motion) {
+                (true, _) => MouseEventKind::Release(button),
+                (false, true) => MouseEventKind::Drag(button),
+                (false, false) => MouseEventKind::Press(button),
+            }
+        }
+    };
+
+    Some(MouseEvent { kind, x: coord(1)?, y: coord(2)?, modifiers })
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse() {
+    fn parse_all(input: &[&[u8]]) -> Vec<Event> {
+        let mut parser = InputParser::new();
+        let mut events = Vec::new();
+        for chunk in input {
+            parser.feed(chunk);
+            events.extend(std::iter::from_fn(|| parser.next_event()));
+        }
+        events
+    }
+
+    let key = |code, modifiers| Event::Key(KeyEvent::new(code, modifiers));
+    let none = Modifiers::empty();
+
+    assert_eq!(
+        parse_all(&[b"a\x01\r\xc3", b"\xa9\x1b[1;5A\x1b", b"[3~"]),
+        [
+            key(KeyCode::Char('a'), none),
+            key(KeyCode::Char('a'), Modifiers::CTRL),
+            key(KeyCode::Enter, none),
+            key(KeyCode::Char('é'), none),
+            key(KeyCode::Up, Modifiers::CTRL),
+            key(KeyCode::Delete, none),
+        ]
+    );
+
+    // Kitty keys, with an event type and associated text
+    let mut event = KeyEvent::new(KeyCode::Char('a'), Modifiers::SHIFT);
+    event.kind = KeyEventKind::Release;
+    event.shifted_key = Some('A');
+    event.text = Some("A".to_owned());
+    assert_eq!(
+        parse_all(&[b"\x1b[97:65;2:3;65u\x1b[27u"]),
+        [Event::Key(event), key(KeyCode::Escape, none)]
+    );
+
+    assert_eq!(
+        parse_all(&[b"\x1b[<0;10;5M\x1b[<64;1;1M\x1b[I"]),
+        [
+            Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Press(MouseButton::Left),
+                x: 9,
+                y: 4,
+                modifiers: none,
+            }),
+            Event::Mouse(MouseEvent {
+                kind: MouseEventKind::ScrollUp,
+                x: 0,
+                y: 0,
+                modifiers: none
+            }),
+            Event::FocusGained,
+        ]
+    );
+
+    // Pastes and replies, split at awkward places
+    assert_eq!(
+        parse_all(&[
+            b"\x1b[200~hi\x1b[A\x1b[20",
+            b"1~\x1b[?62;22c\x1b]11;rgb:0/0/0\x1b",
+            b"\\"
+        ]),
+        [
+            Event::Paste("hi\x1b[A".to_owned()),
+            Event::PrimaryDeviceAttributes(vec![62, 22]),
+            Event::Palette(PaletteReply {
+                entry: crate::PaletteEntry::Background,
+                color: crate::Color::Rgb(0, 0, 0),
+            }),
+        ]
+    );
+
+    // Pending escapes
+    let mut parser = InputParser::new();
+    parser.feed(b"\x1b");
+    assert_eq!(parser.next_event(), None);
+    assert!(parser.has_pending_escape());
+    assert_eq!(parser.flush(), Some(key(KeyCode::Escape, none)));
+    assert_eq!(parser.flush(), None);
+
+    parser.feed(b"\x1b[");
+    assert_eq!(parser.next_event(), None);
+    assert_eq!(
+        parser.flush(),
+        Some(key(KeyCode::Char('['), Modifiers::ALT))
+    );
+
+    parser.feed(b"\x1bx\x1b\x1b[B");
+    assert_eq!(
+        parser.next_event(),
+        Some(key(KeyCode::Char('x'), Modifiers::ALT))
+    );
+    assert_eq!(
+        parser.next_event(),
+        Some(key(KeyCode::Down, Modifiers::ALT))
+    );
+
+    // `Alt-]` and `Alt-P` don't start strings, unless followed by what a reply would be
+    parser.feed(b"\x1b]x\x1bPa");
+    assert_eq!(
+        parser.next_event(),
+        Some(key(KeyCode::Char(']'), Modifiers::ALT))
+    );
+    assert_eq!(parser.next_event(), Some(key(KeyCode::Char('x'), none)));
+    assert_eq!(
+        parser.next_event(),
+        Some(key(KeyCode::Char('P'), Modifiers::ALT))
+    );
+    assert_eq!(parser.next_event(), Some(key(KeyCode::Char('a'), none)));
+    parser.feed(b"\x1b]1");
+    assert_eq!(parser.next_event(), None);
+    assert!(parser.has_pending_escape());
+    assert_eq!(parser.flush(), Some(key(KeyCode::Escape, none)));
+    assert_eq!(parser.flush(), Some(key(KeyCode::Char(']'), none)));
+    assert_eq!(parser.flush(), Some(key(KeyCode::Char('1'), none)));
+
+    // A long run of escapes doesn't nest `Alt`s
+    parser.feed(&[ESC; 200_000]);
+    parser.feed(b"a");
+    for _ in 0..199_998 {
+        assert_eq!(parser.next_event(), Some(key(KeyCode::Escape, none)));
+    }
+    assert_eq!(
+        parser.next_event(),
+        Some(key(KeyCode::Char('a'), Modifiers::ALT))
+    );
+    assert_eq!(parser.next_event(), None);
+}
@@ -0,0 +1,200 @@
+//! Progressive enhancement of keyboard input, via the [Kitty keyboard protocol]
+//!
+//! The protocol fixes long-standing ambiguities in how terminals encode key presses -- e.g.,
+//! `Ctrl-I` and `Tab` send the same byte -- and allows reporting things that legacy encodings
+//! can't represent at all, like key releases. Programs opt in to the enhancements they want by
+//! pushing a set of [`KeyboardFlags`] onto the terminal's stack, and popping them on exit.
+//!
+//! [Kitty keyboard protocol]: https://sw.kovidgoyal.net/kitty/keyboard-protocol/
+
+use std::fmt::{self, Debug, Formatter};
+use std::ops::{BitOr, BitOrAssign};
+
+#[cfg(unix)]
+use crate::tty;
+#[cfg(unix)]
+use std::{io, os::unix::io::AsRawFd, time::Duration, time::Instant};
+
+/// A set of progressive enhancements from the Kitty keyboard protocol
+///
+/// Flags are combined with `|`, e.g.:
+///
+/// ```
+/// use dymium_term::KeyboardFlags;
+///
+/// let flags = KeyboardFlags::DISAMBIGUATE_ESCAPE_CODES | KeyboardFlags::REPORT_EVENT_TYPES;
+/// assert!(flags.contains(KeyboardFlags::REPORT_EVENT_TYPES));
+/// ```
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct KeyboardFlags(u8);
+
+/// A reply from the terminal to [`KeyboardCommand::QueryFlags`](crate::KeyboardCommand::QueryFlags)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyboardReply {
+    /// The flags that are currently enabled
+    pub flags: KeyboardFlags,
+}
+
+impl KeyboardFlags {
+    /// Use `CSI u` sequences for keys that would otherwise be ambiguous -- e.g., `Esc`,
+    /// `Alt-<KEY>`, and `Ctrl-I` vs `Tab`
+    pub const DISAMBIGUATE_ESCAPE_CODES: Self = KeyboardFlags(1);
+    /// Report key repeat and release events, in addition to presses
+    pub const REPORT_EVENT_TYPES: Self = KeyboardFlags(2);
+    /// Report the shifted key and the key in the standard (PC-101) layout, alongside the key
+    /// itself
+    pub const REPORT_ALTERNATE_KEYS: Self = KeyboardFlags(4);
+    /// Use `CSI u` sequences for all keys, including those that produce text -- e.g. `a` and
+    /// `Enter`
+    pub const REPORT_ALL_KEYS_AS_ESCAPE_CODES: Self = KeyboardFlags(8);
+    /// Report the text produced by the key, alongside the key itself
+    ///
+    /// This is only meaningful in combination with `REPORT_ALL_KEYS_AS_ESCAPE_CODES`.
+    pub const REPORT_ASSOCIATED_TEXT: Self = KeyboardFlags(16);
+
+    const ALL: [(Self, &'static str); 5] = [
+        (Self::DISAMBIGUATE_ESCAPE_CODES, "DISAMBIGUATE_ESCAPE_CODES"),
+        (Self::REPORT_EVENT_TYPES, "REPORT_EVENT_TYPES"),
+        (Self::REPORT_ALTERNATE_KEYS, "REPORT_ALTERNATE_KEYS"),
+        (
+            Self::REPORT_ALL_KEYS_AS_ESCAPE_CODES,
+            "REPORT_ALL_KEYS_AS_ESCAPE_CODES",
+        ),
+        (Self::REPORT_ASSOCIATED_TEXT, "REPORT_ASSOCIATED_TEXT"),
+    ];
+
+    /// Returns the empty set of flags, i.e. legacy keyboard handling
+    pub const fn empty() -> Self {
+        KeyboardFlags(0)
+    }
+
+    /// Returns the raw value of the flags, as used by the protocol
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Creates the flags from their raw value, discarding any unknown bits
+    pub const fn from_bits_truncate(bits: u8) -> Self {
+        KeyboardFlags(bits & 0b11111)
+    }
+
+    /// Returns whether no flags are set
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns whether all of the flags in `other` are also set in `self`
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for KeyboardFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        KeyboardFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for KeyboardFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+impl Debug for KeyboardFlags {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let names = Self::ALL
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name);
+
+        f.write_str("KeyboardFlags(")?;
+        for (i, name) in names.enumerate() {
+            if i != 0 {
+                f.write_str(" | ")?;
+            }
+            f.write_str(name)?;
+        }
+        f.write_str(")")
+    }
+}
+
+#[cfg(unix)]
+impl KeyboardFlags {
+    /// Detects whether the terminal supports the Kitty keyboard protocol, returning the currently
+    /// enabled flags if it does
+    ///
+    /// Like [`Background::detect`], this sends the query alongside a request for the primary device
+    /// attributes, so that we can stop waiting early if the terminal ignores the query. Otherwise,
+    /// we wait up to `timeout` for replies. Canonical mode and echoing are temporarily disabled,
+    /// and any other input received in the meantime is discarded.
+    ///
+    /// Returns `Ok(None)` if the terminal doesn't support the protocol.
+    ///
+    /// [`Background::detect`]: crate::Background::detect
+    pub fn detect<T: io::Write + AsRawFd>(
+        tty: &mut T,
+        timeout: Duration,
+    ) -> io::Result<Option<Self>> {
+        let fd = tty.as_raw_fd();
+        let _guard = tty::NoEchoGuard::new(fd)?;
+
+        tty.write_all(b"\x1b[?u\x1b[c")?;
+        tty.flush()?;
+
+        let mut buf = Vec::new();
+        let deadline = Instant::now() + timeout;
+        tty::read_until(fd, &mut buf, deadline, |b| tty::find_da1_reply(b).is_some())?;
+
+        // The reply to the query must come before the DA1 reply; anything after it is unrelated.
+        let end = tty::find_da1_reply(&buf).unwrap_or(buf.len());
+        let reply = tty::find(&buf[..end], b"\x1b[?").and_then(|start| {
+            let rest = &buf[start..end];
+            let len = rest.iter().position(|&b| b == b'u')? + 1;
+            KeyboardReply::parse(&rest[..len])
+        });
+
+        Ok(reply.map(|r| r.flags))
+    }
+}
+
+impl KeyboardReply {
+    /// Parses the terminal's reply to a query of the current flags
+    ///
+    /// The reply has the form `ESC[?<FLAGS>u`. Returns `None` if the reply is malformed.
+    pub fn parse(reply: &[u8]) -> Option<Self> {
+        let params = reply.strip_prefix(b"\x1b[?")?.strip_suffix(b"u")?;
+        Self::parse_csi_params(params)
+    }
+
+    /// Parses the reply from the parameters of the CSI sequence -- i.e., everything between
+    /// `ESC[?` and the final `u`
+    pub(crate) fn parse_csi_params(params: &[u8]) -> Option<Self> {
+        if params.is_empty() || !params.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+
+        let bits = std::str::from_utf8(params).ok()?.parse().ok()?;
+        Some(KeyboardReply { flags: KeyboardFlags::from_bits_truncate(bits) })
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_reply() {
+    let reply = KeyboardReply::parse(b"\x1b[?0u").unwrap();
+    assert!(reply.flags.is_empty());
+
+    let reply = KeyboardReply::parse(b"\x1b[?3u").unwrap();
+    let flags = KeyboardFlags::DISAMBIGUATE_ESCAPE_CODES | KeyboardFlags::REPORT_EVENT_TYPES;
+    assert_eq!(reply.flags, flags);
+    assert_eq!(
+        format!("{flags:?}"),
+        "KeyboardFlags(DISAMBIGUATE_ESCAPE_CODES | REPORT_EVENT_TYPES)"
+    );
+
+    assert_eq!(KeyboardReply::parse(b"\x1b[?u"), None);
+    assert_eq!(KeyboardReply::parse(b"\x1b[?1;2c"), None);
+}
@@ -2,17 +2,48 @@
 //!
 //! This crate includes a number of things relevant to interacting with terminals, primarily based
 //! around ANSI escape codes. However, other capabilities are also implemented here (e.g, enabling
-//! "raw" mode, detecting the [Kitty keyboard protocol]).
+//! "raw" mode with [`RawModeGuard`], detecting the [Kitty keyboard protocol] with
+//! [`KeyboardFlags::detect`]).
 //!
 //! [Kitty keyboard protocol]: https://sw.kovidgoyal.net/kitty/keyboard-protocol/
 
 #![deny(missing_docs, rustdoc::broken_intra_doc_links)]
 
+mod background;
 pub mod capinfo;
+mod clipboard;
 mod cmd;
 mod color;
+mod hyperlink;
+mod input;
+mod keyboard;
+mod motion;
+mod palette;
+mod probe;
+mod screen;
 mod style;
+mod sync;
+#[cfg(unix)]
+mod tty;
+pub mod vt;
 
-pub use cmd::{Command, CursorCommand, ScrollCommand};
+pub use background::{Background, Brightness};
+pub use clipboard::{ClipboardReply, ClipboardSelection};
+pub use cmd::{
+    ClipboardCommand, Command, CursorCommand, CursorShape, EditCommand, EraseCommand,
+    KeyboardCommand, Mode, PaletteCommand, ScrollCommand, WindowCommand, WriteCommandError,
+};
 pub use color::{Color, ColorParseError};
+pub use hyperlink::Hyperlink;
+pub use input::{
+    Event, InputParser, KeyCode, KeyEvent, KeyEventKind, Modifiers, MouseButton, MouseEvent,
+    MouseEventKind,
+};
+pub use keyboard::{KeyboardFlags, KeyboardReply};
+pub use palette::{PaletteEntry, PaletteReply};
+pub use probe::{ModeReport, ModeState, ProbeReport};
+pub use screen::{Cell, Screen};
 pub use style::{Style, UnderlineShape, UnderlineStyle};
+pub use sync::SyncedUpdate;
+#[cfg(unix)]
+pub use tty::{InputMode, RawModeGuard};
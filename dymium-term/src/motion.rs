@@ -0,0 +1,139 @@
+//! Picking the shortest sequence to move the cursor, for [`CursorCommand::MoveFrom`]
+//!
+//! This is roughly what ncurses' `mvcur` does: we try each way of getting from one position to
+//! the other -- absolute movement, relative movement, control characters, or combinations of them
+//! -- and keep whichever takes the fewest bytes.
+//!
+//! [`CursorCommand::MoveFrom`]: crate::CursorCommand::MoveFrom
+
+use crate::capinfo::TermCap;
+use crate::{Command, CursorCommand, WriteCommandError};
+
+/// The most repetitions of a single-byte control character (`\b` or `\n`) that we'll consider
+///
+/// Anything more is always longer than the equivalent escape sequence.
+const MAX_REPEATS: u16 = 8;
+
+/// Returns the shortest sequence of bytes that moves the cursor from `from` to `to`, each given as
+/// `(x, y)`
+///
+/// Returns an error if there's no way to make the movement with the terminal's capabilities.
+pub(crate) fn cheapest_motion(
+    from: (u16, u16),
+    to: (u16, u16),
+    caps: &TermCap,
+) -> Result<Vec<u8>, WriteCommandError> {
+    let ((from_x, from_y), (to_x, to_y)) = (from, to);
+
+    let mut candidates = Vec::new();
+    if let Some(cup) = encode(CursorCommand::MoveTo { x: Some(to_x), y: Some(to_y) }, caps) {
+        candidates.push(cup);
+    }
+
+    // Movements that keep the column, followed by movements that keep the row
+    for vertical in vertical_motions(from_y, to_y, caps) {
+        for horizontal in horizontal_motions(from_x, to_x, caps) {
+            candidates.push([&vertical[..], &horizontal].concat());
+        }
+    }
+
+    // `\r` followed by `\n` for each line. We can't use `\n` without `\r` first, because output
+    // processing might turn it into `\r\n`, which would make the column unpredictable.
+    if to_y > from_y && to_y - from_y <= MAX_REPEATS {
+        let mut prefix = vec![b'\r'];
+        prefix.resize(1 + (to_y - from_y) as usize, b'\n');
+        for horizontal in horizontal_motions(0, to_x, caps) {
+            candidates.push([&prefix[..], &horizontal].concat());
+        }
+    }
+
+    candidates
+        .into_iter()
+        .min_by_key(|c| c.len())
+        .ok_or(WriteCommandError::Unsupported("cursor movement"))
+}
+
+/// Returns the ways to move the cursor from column `from` to `to`, without changing the row
+fn horizontal_motions(from: u16, to: u16, caps: &TermCap) -> Vec<Vec<u8>> {
+    if from == to {
+        return vec![Vec::new()];
+    }
+
+    let mut motions = Vec::new();
+    if let Some(x) = delta(from, to) {
+        motions.extend(encode(CursorCommand::MoveRelative { x, y: 0 }, caps));
+    }
+    motions.extend(encode(CursorCommand::MoveTo { x: Some(to), y: None }, caps));
+
+    if to < from && from - to <= MAX_REPEATS {
+        motions.push(vec![0x08; (from - to) as usize]);
+    }
+
+    match to {
+        0 => motions.push(vec![b'\r']),
+        _ => {
+            if let Some(x) = delta(0, to) {
+                let forward = CursorCommand::MoveRelative { x, y: 0 };
+                motions.extend(encode(forward, caps).map(|f| [&b"\r"[..], &f].concat()));
+            }
+        }
+    }
+
+    motions
+}
+
+/// Returns the ways to move the cursor from row `from` to `to` with escape sequences, without
+/// changing the column
+fn vertical_motions(from: u16, to: u16, caps: &TermCap) -> Vec<Vec<u8>> {
+    if from == to {
+        return vec![Vec::new()];
+    }
+
+    let relative = delta(from, to).map(|y| CursorCommand::MoveRelative { x: 0, y });
+    let absolute = CursorCommand::MoveTo { x: None, y: Some(to) };
+    relative
+        .and_then(|relative| encode(relative, caps))
+        .into_iter()
+        .chain(encode(absolute, caps))
+        .collect()
+}
+
+/// Returns the distance from `from` to `to`, if it fits in the amount for
+/// [`CursorCommand::MoveRelative`]
+fn delta(from: u16, to: u16) -> Option<i16> {
+    i16::try_from(to as i32 - from as i32).ok()
+}
+
+fn encode(cmd: CursorCommand, caps: &TermCap) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    Command::Cursor(cmd).write_to(&mut buf, caps).ok()?;
+    Some(buf)
+}
+
+#[cfg(test)]
+#[test]
+fn test_cheapest_motion() {
+    let caps = crate::capinfo::test_caps("xterm");
+    let motion = |from, to| String::from_utf8(cheapest_motion(from, to, &caps).unwrap()).unwrap();
+
+    assert_eq!(motion((5, 5), (5, 5)), "");
+    assert_eq!(motion((5, 5), (3, 5)), "\x08\x08");
+    assert_eq!(motion((5, 5), (0, 5)), "\r");
+    assert_eq!(motion((5, 5), (0, 6)), "\r\n");
+    assert_eq!(motion((5, 5), (6, 5)), "\x1b[C");
+    assert_eq!(motion((5, 5), (5, 2)), "\x1b[3A");
+    assert_eq!(motion((5, 5), (1, 6)), "\r\n\x1b[C");
+    assert_eq!(motion((5, 5), (40, 0)), "\x1b[1;41H");
+    assert_eq!(motion((50, 5), (3, 5)), "\x1b[4G");
+    assert_eq!(motion((50, 20), (50, 0)), "\x1b[d");
+    assert_eq!(motion((0, 0), (40000, 0)), "\x1b[40001G");
+    assert_eq!(motion((40000, 0), (40001, 0)), "\x1b[C");
+    assert_eq!(motion((0, 65535), (0, 0)), "\x1b[H");
+
+    // Without escape sequences, only control characters are available
+    let mut caps = caps;
+    caps.cursor.basic_movement = false;
+    let result = cheapest_motion((5, 5), (6, 5), &caps);
+    assert!(matches!(result, Err(WriteCommandError::Unsupported(_))));
+    assert_eq!(cheapest_motion((5, 5), (0, 7), &caps).unwrap(), b"\r\n\n");
+}
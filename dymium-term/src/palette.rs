@@ -0,0 +1,128 @@
+//! Changing the terminal's color palette, via OSC 4, 10, 11, 12, and friends
+
+use crate::Color;
+use std::io;
+
+/// An entry in the terminal's color palette, for [`PaletteCommand`](crate::PaletteCommand)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PaletteEntry {
+    /// One of the 256 indexed colors -- i.e., the color used for [`Color::Fixed`]
+    Indexed(u8),
+    /// The default foreground color, used when no other color is set
+    Foreground,
+    /// The default background color, used when no other color is set
+    Background,
+    /// The color of the cursor
+    Cursor,
+}
+
+/// A reply from the terminal to [`PaletteCommand::Query`](crate::PaletteCommand::Query)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PaletteReply {
+    /// The palette entry that was queried
+    pub entry: PaletteEntry,
+    /// The current value of the palette entry
+    ///
+    /// This is always a [`Color::Rgb`].
+    pub color: Color,
+}
+
+impl PaletteEntry {
+    /// Returns the OSC number used to set or query the entry
+    fn osc_number(self) -> u8 {
+        match self {
+            PaletteEntry::Indexed(_) => 4,
+            PaletteEntry::Foreground => 10,
+            PaletteEntry::Background => 11,
+            PaletteEntry::Cursor => 12,
+        }
+    }
+
+    /// Writes the start of the escape sequence, up to where the color (or `?`) should go
+    fn write_prefix<W: io::Write + ?Sized>(self, out: &mut W) -> io::Result<()> {
+        match self {
+            PaletteEntry::Indexed(n) => write!(out, "\x1b]4;{n};"),
+            _ => write!(out, "\x1b]{};", self.osc_number()),
+        }
+    }
+
+    /// Writes the escape sequence that sets the entry to the color
+    pub(crate) fn write_set<W: io::Write + ?Sized>(
+        self,
+        out: &mut W,
+        color: Color,
+    ) -> io::Result<()> {
+        self.write_prefix(out)?;
+        color.write_x11_spec(out)?;
+        out.write_all(b"\x1b\\")
+    }
+
+    /// Writes the escape sequence that requests the current value of the entry
+    pub(crate) fn write_query<W: io::Write + ?Sized>(self, out: &mut W) -> io::Result<()> {
+        self.write_prefix(out)?;
+        out.write_all(b"?\x1b\\")
+    }
+
+    /// Writes the escape sequence that resets the entry to the terminal's default
+    pub(crate) fn write_reset<W: io::Write + ?Sized>(self, out: &mut W) -> io::Result<()> {
+        match self {
+            PaletteEntry::Indexed(n) => write!(out, "\x1b]104;{n}\x1b\\"),
+            _ => write!(out, "\x1b]{}\x1b\\", self.osc_number() as u16 + 100),
+        }
+    }
+}
+
+impl PaletteReply {
+    /// Parses the terminal's reply to a palette query
+    ///
+    /// The reply has the form `ESC]4;<N>;<COLOR>` (for indexed colors) or `ESC]<N>;<COLOR>` (for
+    /// the default foreground, background, or cursor color) followed by either `ESC\` or `BEL`.
+    /// The terminator may be omitted. Returns `None` if the reply is malformed.
+    pub fn parse(reply: &[u8]) -> Option<Self> {
+        let body = reply.strip_prefix(b"\x1b]")?;
+        let body = body
+            .strip_suffix(b"\x1b\\")
+            .or_else(|| body.strip_suffix(b"\x07"))
+            .unwrap_or(body);
+
+        Self::parse_osc_body(body)
+    }
+
+    /// Parses the reply from the body of the OSC sequence -- i.e., everything after `ESC]` and
+    /// before the terminator
+    pub(crate) fn parse_osc_body(body: &[u8]) -> Option<Self> {
+        let mut parts = body.splitn(3, |&b| b == b';');
+        let (entry, spec) = match parts.next()? {
+            b"4" => {
+                let index = std::str::from_utf8(parts.next()?).ok()?.parse().ok()?;
+                (PaletteEntry::Indexed(index), parts.next()?)
+            }
+            b"10" => (PaletteEntry::Foreground, parts.next()?),
+            b"11" => (PaletteEntry::Background, parts.next()?),
+            b"12" => (PaletteEntry::Cursor, parts.next()?),
+            _ => return None,
+        };
+
+        // For the dynamic colors, anything after another semicolon is the next color in the
+        // sequence (e.g. `ESC]10;<FG>;<BG>`), which we ignore.
+        let spec = spec.split(|&b| b == b';').next()?;
+        let color = Color::parse_x11_spec(spec)?;
+        Some(PaletteReply { entry, color })
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_reply() {
+    let reply = PaletteReply::parse(b"\x1b]4;12;rgb:5c5c/5c5c/ffff\x1b\\").unwrap();
+    assert_eq!(reply.entry, PaletteEntry::Indexed(12));
+    assert_eq!(reply.color, Color::Rgb(0x5c, 0x5c, 0xff));
+
+    let reply = PaletteReply::parse(b"\x1b]11;rgb:0000/0000/0000\x07").unwrap();
+    assert_eq!(reply.entry, PaletteEntry::Background);
+    assert_eq!(reply.color, Color::Rgb(0, 0, 0));
+
+    assert_eq!(PaletteReply::parse(b"\x1b]4;256;rgb:0/0/0\x07"), None);
+    assert_eq!(PaletteReply::parse(b"\x1b]13;rgb:0/0/0\x07"), None);
+    assert_eq!(PaletteReply::parse(b"\x1b]11;#\x07"), None);
+}
@@ -0,0 +1,220 @@
+//! Probing the terminal at runtime, to refine the capabilities from `capdata.yaml`
+
+use crate::capinfo::{ModeCap, TermCap};
+use crate::{Event, InputParser, KeyboardFlags, Mode};
+
+#[cfg(unix)]
+use crate::tty;
+#[cfg(unix)]
+use std::{io, os::unix::io::AsRawFd, time::Duration, time::Instant};
+
+/// Whether a terminal mode is enabled, from a [`ModeReport`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ModeState {
+    /// The terminal doesn't recognize the mode
+    NotRecognized,
+    /// The mode is enabled
+    Set,
+    /// The mode is disabled
+    Reset,
+    /// The mode is enabled, and can't be disabled
+    PermanentlySet,
+    /// The mode is disabled, and can't be enabled
+    PermanentlyReset,
+}
+
+/// The terminal's reply to [`Command::QueryMode`](crate::Command::QueryMode)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ModeReport {
+    /// The number of the (DEC private) mode that the reply is for -- e.g., `2026` for
+    /// [`Mode::SynchronizedOutput`]
+    pub mode: u16,
+    /// Whether the mode is enabled
+    pub state: ModeState,
+}
+
+/// What the terminal reported about itself, from [`ProbeReport::detect`]
+///
+/// The static capabilities from `capdata.yaml` can't account for the terminal's exact version or
+/// the user's settings, nor for a multiplexer (like tmux) sitting in between. The report can be
+/// used to correct them with [`adjust_caps`](Self::adjust_caps).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbeReport {
+    /// The parameters of the reply to the primary device attributes request (DA1)
+    ///
+    /// The first is the terminal's conformance level (e.g., `62` for a VT220), and the rest are
+    /// the features it supports.
+    pub primary_attributes: Vec<u16>,
+    /// The parameters of the reply to the secondary device attributes request (DA2), if there was
+    /// one
+    ///
+    /// These are nominally the terminal's type, its version, and a hardware option -- but many
+    /// terminals report something arbitrary, so they're best used for display only.
+    pub secondary_attributes: Option<Vec<u16>>,
+    /// The terminal's name and version from XTVERSION, like `XTerm(390)` or `tmux 3.4`
+    pub version: Option<String>,
+    /// The state of each [`Mode`] that the terminal replied about
+    pub modes: Vec<(Mode, ModeState)>,
+    /// The current Kitty keyboard flags, if the terminal replied to the query
+    pub keyboard_flags: Option<KeyboardFlags>,
+}
+
+impl ModeState {
+    /// Returns whether the mode can be enabled and disabled
+    pub fn is_changeable(self) -> bool {
+        matches!(self, ModeState::Set | ModeState::Reset)
+    }
+}
+
+impl ModeReport {
+    /// Parses the terminal's reply to a mode query
+    ///
+    /// The reply has the form `ESC[?<MODE>;<STATE>$y`. Returns `None` if the reply is malformed.
+    pub fn parse(reply: &[u8]) -> Option<Self> {
+        let params = reply.strip_prefix(b"\x1b[?")?.strip_suffix(b"$y")?;
+        Self::parse_csi_params(params)
+    }
+
+    /// Parses the reply from the parameters of the CSI sequence -- i.e., everything between `ESC[?`
+    /// and the final `$y`
+    pub(crate) fn parse_csi_params(params: &[u8]) -> Option<Self> {
+        let params = std::str::from_utf8(params).ok()?;
+        let (mode, state) = params.split_once(';')?;
+        if mode.is_empty() || !mode.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let state = match state {
+            "0" => ModeState::NotRecognized,
+            "1" => ModeState::Set,
+            "2" => ModeState::Reset,
+            "3" => ModeState::PermanentlySet,
+            "4" => ModeState::PermanentlyReset,
+            _ => return None,
+        };
+        Some(ModeReport { mode: mode.parse().ok()?, state })
+    }
+}
+
+impl ProbeReport {
+    /// Probes the terminal by sending queries and collecting the replies
+    ///
+    /// We request the secondary device attributes (DA2) and the terminal's version (XTVERSION),
+    /// query every [`Mode`] and the Kitty keyboard flags, and finally request the primary device
+    /// attributes (DA1). Essentially all terminals reply to DA1, and replies arrive in the order
+    /// of their requests -- so once the reply to DA1 arrives, we have everything the terminal was
+    /// going to tell us. Otherwise, we wait up to `timeout` for it.
+    ///
+    /// While waiting, canonical mode and echoing are temporarily disabled for the tty, if they
+    /// weren't already. Any other input received in the meantime (e.g., key presses) is
+    /// discarded, so this is best done on startup, before handling user input.
+    ///
+    /// Returns `Ok(None)` if there was no reply to DA1 before the timeout.
+    #[cfg(unix)]
+    pub fn detect<T: io::Write + AsRawFd>(
+        tty: &mut T,
+        timeout: Duration,
+    ) -> io::Result<Option<Self>> {
+        let fd = tty.as_raw_fd();
+        let _guard = tty::NoEchoGuard::new(fd)?;
+
+        tty.write_all(b"\x1b[>c\x1b[>q")?;
+        for mode in Mode::ALL {
+            write!(tty, "\x1b[?{}$p", mode.number())?;
+        }
+        tty.write_all(b"\x1b[?u\x1b[c")?;
+        tty.flush()?;
+
+        let mut buf = Vec::new();
+        let deadline = Instant::now() + timeout;
+        tty::read_until(fd, &mut buf, deadline, |b| tty::find_da1_reply(b).is_some())?;
+
+        Ok(Self::from_replies(&buf))
+    }
+
+    /// Collects the replies read from the terminal, up to the reply to DA1
+    ///
+    /// Returns `None` if there's no reply to DA1.
+    pub(crate) fn from_replies(bytes: &[u8]) -> Option<Self> {
+        let mut parser = InputParser::new();
+        parser.feed(bytes);
+
+        let mut report = ProbeReport::default();
+        while let Some(event) = parser.next_event() {
+            match event {
+                Event::PrimaryDeviceAttributes(params) => {
+                    report.primary_attributes = params;
+                    return Some(report);
+                }
+                Event::SecondaryDeviceAttributes(params) => {
+                    report.secondary_attributes = Some(params)
+                }
+                Event::TerminalVersion(version) => report.version = Some(version),
+                Event::ModeReport(reply) => {
+                    if let Some(mode) = Mode::ALL.into_iter().find(|m| m.number() == reply.mode) {
+                        report.modes.push((mode, reply.state));
+                    }
+                }
+                Event::KeyboardFlags(flags) => report.keyboard_flags = Some(flags),
+                _ => (),
+            }
+        }
+
+        None
+    }
+
+    /// Returns `caps`, adjusted to match what the terminal reported
+    ///
+    /// Each mode that the terminal replied about is marked as supported if it can be toggled, and
+    /// unsupported otherwise -- so this can both remove and add support. Terminals that don't
+    /// support mode queries at all don't reply, leaving the modes as-is. The Kitty keyboard
+    /// protocol is supported exactly when the terminal replied to its query.
+    ///
+    /// Because the replies come from whatever is on the other end of the tty, this also accounts
+    /// for multiplexers like tmux, which reply based on what *they* support.
+    pub fn adjust_caps(&self, caps: &TermCap) -> TermCap {
+        let mut caps = *caps;
+        for &(mode, state) in &self.modes {
+            *mode_cap(&mut caps.mode, mode) = state.is_changeable();
+        }
+        caps.keyboard.kitty_protocol = self.keyboard_flags.is_some();
+        caps
+    }
+}
+
+/// Returns the capability for toggling the mode
+fn mode_cap(caps: &mut ModeCap, mode: Mode) -> &mut bool {
+    match mode {
+        Mode::AlternateScreen => &mut caps.alternate_screen,
+        Mode::BracketedPaste => &mut caps.bracketed_paste,
+        Mode::FocusEvents => &mut caps.focus_events,
+        Mode::Autowrap => &mut caps.autowrap,
+        Mode::SynchronizedOutput => &mut caps.synchronized_output,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_probe_report() {
+    let reply = ModeReport::parse(b"\x1b[?2026;2$y").unwrap();
+    assert_eq!(reply, ModeReport { mode: 2026, state: ModeState::Reset });
+    assert_eq!(ModeReport::parse(b"\x1b[?2026;5$y"), None);
+    assert_eq!(ModeReport::parse(b"\x1b[?;1$y"), None);
+
+    // tmux, in front of a terminal that supports the Kitty keyboard protocol
+    let replies = b"\x1b[>84;0;0c\x1bP>|tmux 3.4\x1b\\\x1b[?1049;2$y\x1b[?2026;0$y\x1b[?62;22c";
+    let report = ProbeReport::from_replies(replies).unwrap();
+    assert_eq!(report.primary_attributes, [62, 22]);
+    assert_eq!(report.secondary_attributes, Some(vec![84, 0, 0]));
+    assert_eq!(report.version.as_deref(), Some("tmux 3.4"));
+    assert_eq!(report.keyboard_flags, None);
+
+    let caps = report.adjust_caps(&crate::capinfo::test_caps("kitty"));
+    assert!(caps.mode.alternate_screen);
+    assert!(!caps.mode.synchronized_output);
+    assert!(caps.mode.bracketed_paste);
+    assert!(!caps.keyboard.kitty_protocol);
+
+    // Without the reply to DA1, we don't know whether everything has arrived
+    assert_eq!(ProbeReport::from_replies(b"\x1b[?0u\x1b[>1;2c"), None);
+}
@@ -0,0 +1,601 @@
+//! Double-buffered rendering of styled cells
+
+use crate::capinfo::TermCap;
+use crate::{Command, CursorCommand, EraseCommand, ScrollCommand, Style, WriteCommandError};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops::Range;
+
+/// A single cell on the screen: a character and the style it's displayed with
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Cell {
+    /// The character displayed in the cell
+    pub c: char,
+    /// The style that the character is displayed with
+    pub style: Style,
+}
+
+/// Double-buffered grid of [`Cell`]s, for drawing to the terminal without repainting everything
+///
+/// The application draws into the back buffer (with [`set`], [`print`], etc.), and then calls
+/// [`flush`] to write it to the terminal. `Screen` keeps a copy of what was last written -- the
+/// front buffer -- and only emits the commands needed to update the cells that changed.
+///
+/// The first flush (and any flush after [`resize`] or [`invalidate`]) clears the terminal and
+/// redraws everything -- by printing every cell, if the terminal can't erase. In between,
+/// `Screen` assumes that it's the only thing writing to the terminal; if something else has, call
+/// [`invalidate`].
+///
+/// Erasing is assumed to fill cells with the current background color, as in essentially all
+/// modern terminals. Every character is assumed to occupy a single cell.
+///
+/// When the new frame is the previous one shifted vertically -- as when scrolling through a
+/// document -- the terminal is asked to scroll the shifted rows, so that only the newly exposed
+/// ones are drawn. This needs [`ScrollCap::basic`], and also [`ScrollCap::set_region`] (or
+/// [`EditCap::lines`]) unless the whole screen moves.
+///
+/// To avoid tearing, the output of `flush` can be wrapped in a [`SyncedUpdate`].
+///
+/// [`set`]: Self::set
+/// [`print`]: Self::print
+/// [`flush`]: Self::flush
+/// [`resize`]: Self::resize
+/// [`invalidate`]: Self::invalidate
+/// [`SyncedUpdate`]: crate::SyncedUpdate
+/// [`ScrollCap::basic`]: crate::capinfo::ScrollCap::basic
+/// [`ScrollCap::set_region`]: crate::capinfo::ScrollCap::set_region
+/// [`EditCap::lines`]: crate::capinfo::EditCap::lines
+#[derive(Debug, Clone)]
+pub struct Screen {
+    width: u16,
+    height: u16,
+    back: Vec<Cell>,
+    front: Vec<Cell>,
+    /// False if the contents of the terminal are unknown, so the next flush must redraw everything
+    front_valid: bool,
+    /// Where the cursor should be displayed after flushing, if it should be visible
+    cursor: Option<(u16, u16)>,
+    /// The state of the terminal, as of the end of the last flush
+    term: TermState,
+}
+
+/// What we know about the state of the terminal; `None` for anything that's unknown
+#[derive(Debug, Copy, Clone, Default)]
+struct TermState {
+    cursor: Option<(u16, u16)>,
+    cursor_visible: Option<bool>,
+    style: Option<Style>,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { c: ' ', style: Style::default() }
+    }
+}
+
+impl Cell {
+    /// Creates a new `Cell` with the character and style
+    pub fn new(c: char, style: Style) -> Self {
+        Cell { c, style }
+    }
+}
+
+impl Screen {
+    /// Creates a new `Screen` with the given size, with blank buffers
+    pub fn new(width: u16, height: u16) -> Self {
+        let len = width as usize * height as usize;
+        Screen {
+            width,
+            height,
+            back: vec![Cell::default(); len],
+            front: vec![Cell::default(); len],
+            front_valid: false,
+            cursor: None,
+            term: TermState::default(),
+        }
+    }
+
+    /// Returns the size of the screen, as `(width, height)`
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Resizes the screen, keeping the contents of the back buffer that are still in bounds
+    ///
+    /// The next flush will redraw everything.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        let mut back = vec![Cell::default(); width as usize * height as usize];
+        for y in 0..height.min(self.height) {
+            for x in 0..width.min(self.width) {
+                back[y as usize * width as usize + x as usize] = self.back[self.index(x, y)];
+            }
+        }
+
+        *self = Screen {
+            back,
+            cursor: self.cursor,
+            ..Screen::new(width, height)
+        };
+    }
+
+    /// Marks the contents of the terminal as unknown, so that the next flush redraws everything
+    ///
+    /// This should be called if something else may have written to the terminal -- e.g., after
+    /// the process is resumed from being suspended.
+    pub fn invalidate(&mut self) {
+        self.front_valid = false;
+        self.term = TermState::default();
+    }
+
+    /// Clears the back buffer, setting every cell to a space with the default style
+    pub fn clear(&mut self) {
+        self.back.fill(Cell::default());
+    }
+
+    /// Returns the cell in the back buffer at the given position, with the top-left corner at
+    /// (0, 0)
+    pub fn get(&self, x: u16, y: u16) -> Option<&Cell> {
+        self.in_bounds(x, y).then(|| &self.back[self.index(x, y)])
+    }
+
+    /// Returns a mutable reference to the cell in the back buffer at the given position
+    pub fn get_mut(&mut self, x: u16, y: u16) -> Option<&mut Cell> {
+        let i = self.index(x, y);
+        self.in_bounds(x, y).then(move || &mut self.back[i])
+    }
+
+    /// Sets the cell in the back buffer at the given position, if it's in bounds
+    pub fn set(&mut self, x: u16, y: u16, cell: Cell) {
+        if let Some(c) = self.get_mut(x, y) {
+            *c = cell;
+        }
+    }
+
+    /// Writes the text into the back buffer with the style, starting at the given position
+    ///
+    /// Text that goes past the end of the row is cut off. Control characters are replaced with
+    /// spaces. Returns the column just after the end of the text.
+    pub fn print(&mut self, x: u16, y: u16, text: &str, style: Style) -> u16 {
+        let mut x = x;
+        for c in text.chars() {
+            if x >= self.width {
+                break;
+            }
+            let c = if c.is_control() { ' ' } else { c };
+            self.set(x, y, Cell { c, style });
+            x += 1;
+        }
+        x
+    }
+
+    /// Sets the position of the cursor after flushing, or hides it if `None`
+    pub fn set_cursor(&mut self, cursor: Option<(u16, u16)>) {
+        self.cursor = cursor;
+    }
+
+    /// Finds the range of rows that changed and an amount to scroll them by (as in
+    /// [`ScrollCommand`]), if scrolling would leave more of them matching the back buffer
+    fn find_scroll(&self) -> Option<(Range<u16>, i16)> {
+        // Each row is hashed once, so that trying every amount doesn't compare every cell each
+        // time. A collision just means a worse choice of scroll -- the rows are still compared
+        // cell-by-cell when they're updated.
+        let hash_rows = |cells: &[Cell]| -> Vec<u64> {
+            let rows = cells.chunks(self.width.max(1) as usize);
+            rows.map(|row| {
+                let mut hasher = DefaultHasher::new();
+                row.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect()
+        };
+        let (front, back) = (hash_rows(&self.front), hash_rows(&self.back));
+
+        let top = (0..front.len()).find(|&y| front[y] != back[y])?;
+        let bottom = (0..front.len()).rfind(|&y| front[y] != back[y])? + 1;
+
+        // Rows in between can still be unchanged, and would have to be redrawn after scrolling
+        let mut best = None;
+        let mut best_matches = (top..bottom).filter(|&y| front[y] == back[y]).count();
+        for amount in 1..bottom - top {
+            let up = (top..bottom - amount)
+                .filter(|&y| back[y] == front[y + amount])
+                .count();
+            let down = (top + amount..bottom)
+                .filter(|&y| back[y] == front[y - amount])
+                .count();
+            if up > best_matches {
+                (best, best_matches) = (Some(amount as i16), up);
+            }
+            if down > best_matches {
+                (best, best_matches) = (Some(-(amount as i16)), down);
+            }
+        }
+
+        best.map(|amount| (top as u16..bottom as u16, amount))
+    }
+
+    /// Scrolls the rows of the front buffer, to match what the terminal does for
+    /// [`ScrollCommand::Bounded`]
+    fn scroll_front(&mut self, y_range: Range<u16>, amount: i16) {
+        let width = self.width as usize;
+        let (start, end) = (y_range.start as usize * width, y_range.end as usize * width);
+        let shift = amount.unsigned_abs() as usize * width;
+        let region = &mut self.front[start..end];
+        let len = region.len();
+        match amount > 0 {
+            true => {
+                region.copy_within(shift.., 0);
+                region[len - shift..].fill(Cell::default());
+            }
+            false => {
+                region.copy_within(..len - shift, shift);
+                region[..shift].fill(Cell::default());
+            }
+        }
+    }
+
+    fn in_bounds(&self, x: u16, y: u16) -> bool {
+        x < self.width && y < self.height
+    }
+
+    fn index(&self, x: u16, y: u16) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    /// Writes the commands that update the terminal to match the back buffer
+    ///
+    /// Afterwards, the front buffer matches the back buffer -- which is left as-is, so that the
+    /// next frame can be drawn on top of it.
+    ///
+    /// If writing fails partway through, the state of the terminal is unknown, so the next flush
+    /// redraws everything (as after [`invalidate`](Self::invalidate)).
+    pub fn flush<W: io::Write + ?Sized>(
+        &mut self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        let result = self.write_changes(out, caps);
+        if result.is_err() {
+            self.invalidate();
+        }
+        result
+    }
+
+    /// Does the work of [`flush`](Self::flush), leaving the front buffer and terminal state
+    /// inconsistent on errors
+    fn write_changes<W: io::Write + ?Sized>(
+        &mut self,
+        out: &mut W,
+        caps: &TermCap,
+    ) -> Result<(), WriteCommandError> {
+        let mut w = Writer { out, caps, term: self.term };
+
+        let any_changed = !self.front_valid || self.front != self.back;
+        if any_changed && w.term.cursor_visible != Some(false) {
+            w.command(Command::Cursor(CursorCommand::Hide))?;
+            w.term.cursor_visible = Some(false);
+        }
+
+        // Without a way to clear the screen, every cell is printed instead
+        let mut redraw_all = false;
+        if !self.front_valid {
+            match caps.erase.in_display {
+                true => {
+                    w.set_style(Style::default())?;
+                    w.command(Command::Erase(EraseCommand::Screen))?;
+                }
+                false => redraw_all = true,
+            }
+            self.front.fill(Cell::default());
+            self.front_valid = true;
+        }
+
+        if let Some((y_range, amount)) = self.find_scroll().filter(|_| !redraw_all) {
+            let full_screen = y_range.start == 0 && y_range.end == self.height;
+            let can_scroll = match full_screen {
+                true => caps.scroll.basic,
+                // Without scroll regions, the command falls back to inserting and deleting lines
+                false => {
+                    (caps.scroll.basic && caps.scroll.set_region)
+                        || (caps.edit.lines && caps.cursor.basic_movement)
+                }
+            };
+            if can_scroll {
+                // Scrolled-in lines are erased with the current background color
+                w.set_style(Style::default())?;
+                let cmd = match full_screen {
+                    true => ScrollCommand::FullScreen { amount },
+                    false => ScrollCommand::Bounded { y_range: y_range.clone(), amount },
+                };
+                w.command(Command::Scroll(cmd))?;
+                if !full_screen {
+                    w.term.cursor = Some((0, 0));
+                }
+                self.scroll_front(y_range, amount);
+            }
+        }
+
+        let width = self.width as usize;
+        for y in 0..self.height {
+            let start = y as usize * width;
+            let row = &self.back[start..start + width];
+            let old_row = &self.front[start..start + width];
+            if redraw_all || row != old_row {
+                w.update_row(y, row, old_row, redraw_all)?;
+            }
+        }
+        self.front.copy_from_slice(&self.back);
+
+        match self.cursor {
+            Some((x, y)) if self.in_bounds(x, y) => {
+                let start = y as usize * width;
+                w.move_cursor(x, y, Some(&self.back[start..start + width]))?;
+                if w.term.cursor_visible != Some(true) {
+                    w.command(Command::Cursor(CursorCommand::Show))?;
+                    w.term.cursor_visible = Some(true);
+                }
+            }
+            _ if w.term.cursor_visible != Some(false) => {
+                w.command(Command::Cursor(CursorCommand::Hide))?;
+                w.term.cursor_visible = Some(false);
+            }
+            _ => (),
+        }
+
+        self.term = w.term;
+        w.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Returns the character that's actually written for `c`, replacing control characters
+fn display_char(c: char) -> char {
+    match c.is_control() {
+        true => ' ',
+        false => c,
+    }
+}
+
+/// Helper for [`Screen::flush`], writing commands while keeping track of the terminal's state
+struct Writer<'a, W: io::Write + ?Sized> {
+    out: &'a mut W,
+    caps: &'a TermCap,
+    term: TermState,
+}
+
+impl<W: io::Write + ?Sized> Writer<'_, W> {
+    fn command(&mut self, cmd: Command) -> Result<(), WriteCommandError> {
+        cmd.write_to(self.out, self.caps)
+    }
+
+    fn set_style(&mut self, style: Style) -> Result<(), WriteCommandError> {
+        let cmd = match self.term.style {
+            Some(current) if current == style => return Ok(()),
+            Some(current) => Command::ChangeStyle { from: current, to: style },
+            None => Command::SetStyle(style),
+        };
+        self.command(cmd)?;
+        self.term.style = Some(style);
+        Ok(())
+    }
+
+    /// Moves the cursor to the position, given the current contents of row `y` on the terminal (if
+    /// known) for reprinting characters
+    fn move_cursor(
+        &mut self,
+        x: u16,
+        y: u16,
+        row: Option<&[Cell]>,
+    ) -> Result<(), WriteCommandError> {
+        let cmd = match self.term.cursor {
+            Some(pos) if pos == (x, y) => return Ok(()),
+            Some(from) => CursorCommand::MoveFrom { from, to: (x, y) },
+            None => CursorCommand::MoveTo { x: Some(x), y: Some(y) },
+        };
+        let mut motion = Vec::new();
+        Command::Cursor(cmd).write_to(&mut motion, self.caps)?;
+
+        // Moving forward along the row can also be done by printing what's already there, if it's
+        // all in the current style.
+        if let (Some((cur_x, cur_y)), Some(row), Some(style)) =
+            (self.term.cursor, row, self.term.style)
+        {
+            let cells = row.get(cur_x as usize..x as usize).unwrap_or(&[]);
+            if cur_y == y && !cells.is_empty() && cells.iter().all(|c| c.style == style) {
+                let reprint: String = cells.iter().map(|c| display_char(c.c)).collect();
+                if reprint.len() < motion.len() {
+                    motion = reprint.into_bytes();
+                }
+            }
+        }
+
+        self.out.write_all(&motion)?;
+        self.term.cursor = Some((x, y));
+        Ok(())
+    }
+
+    /// Prints the cell at column `x` of `row`, on line `y`
+    fn print(&mut self, x: u16, y: u16, row: &[Cell]) -> Result<(), WriteCommandError> {
+        let cell = row[x as usize];
+        self.move_cursor(x, y, Some(row))?;
+        self.set_style(cell.style)?;
+
+        let mut buf = [0; 4];
+        let c = display_char(cell.c);
+        self.out.write_all(c.encode_utf8(&mut buf).as_bytes())?;
+
+        // Printing in the last column leaves the cursor there, but with a pending wrap that some
+        // relative movements don't clear. It's simplest to treat the position as unknown.
+        self.term.cursor = match x as usize + 1 < row.len() {
+            true => Some((x + 1, y)),
+            false => None,
+        };
+        Ok(())
+    }
+
+    /// Writes the changes from `old_row` to `row`, on line `y` -- or the entire row, if `all` is
+    /// true
+    fn update_row(
+        &mut self,
+        y: u16,
+        row: &[Cell],
+        old_row: &[Cell],
+        all: bool,
+    ) -> Result<(), WriteCommandError> {
+        let changed = |x: usize| all || row[x] != old_row[x];
+        if row.is_empty() {
+            return Ok(());
+        }
+
+        // If the end of the row is blank, we can erase it instead of printing each space. Blank
+        // cells have the background color that erasing would fill them with.
+        let last = row[row.len() - 1];
+        let blank_style = Style {
+            background: last.style.background,
+            ..Style::default()
+        };
+        let is_blank = |cell: &Cell| cell.c == ' ' && cell.style == blank_style;
+        let blank_start = row.len() - row.iter().rev().take_while(|c| is_blank(c)).count();
+        let changed_blanks = (blank_start..row.len()).filter(|&x| changed(x)).count();
+        // `ESC[K` is shorter than moving and printing more than a few spaces
+        let erase_from = match self.caps.erase.in_line && changed_blanks > 3 {
+            true => blank_start,
+            false => row.len(),
+        };
+
+        for x in 0..erase_from {
+            if changed(x) {
+                self.print(x as u16, y, row)?;
+            }
+        }
+
+        if erase_from < row.len() {
+            self.move_cursor(erase_from as u16, y, Some(row))?;
+            self.set_style(blank_style)?;
+            self.command(Command::Erase(EraseCommand::ToLineEnd))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_screen() {
+    use crate::vt::VirtualScreen;
+    use crate::Color;
+
+    let caps = crate::capinfo::test_caps("xterm");
+    let mut screen = Screen::new(12, 3);
+    let mut term = VirtualScreen::new(12, 3);
+
+    let red = Style::new().foreground(Some(Color::Fixed(1)));
+    screen.print(0, 0, "hello, world", red);
+    screen.print(2, 2, "abc", Style::new());
+    screen.set_cursor(Some((5, 2)));
+    screen.flush(&mut term, &caps).unwrap();
+
+    assert_eq!(term.text(), "hello, world\n\n  abc");
+    assert_eq!(term.cell(0, 0), Some(&Cell::new('h', red)));
+    assert_eq!(term.cursor(), (5, 2));
+    assert!(term.cursor_visible());
+
+    // Only the changes are written on the next flush
+    screen.print(1, 0, "a", red);
+    screen.print(0, 0, "   ", Style::new());
+    screen.print(3, 2, "X", Style::new());
+    let mut out = Vec::new();
+    screen.flush(&mut out, &caps).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&out),
+        "\x1b[?25l\x1b[H   \x1b[2BXc\x1b[?25h"
+    );
+
+    term.feed(&out);
+    assert_eq!(term.text(), "   lo, world\n\n  aXc");
+
+    // Clearing the end of a row
+    screen.print(4, 0, "        ", Style::new());
+    let mut out = Vec::new();
+    screen.flush(&mut out, &caps).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&out),
+        "\x1b[?25l\x1b[d\x08\x1b[K\x1b[3;6H\x1b[?25h"
+    );
+
+    term.feed(&out);
+    assert_eq!(term.text(), "   l\n\n  aXc");
+    assert_eq!(term.cursor(), (5, 2));
+
+    // Scrolling the whole screen
+    let mut screen = Screen::new(6, 4);
+    let mut term = VirtualScreen::new(6, 4);
+    for y in 0..4 {
+        screen.print(0, y, &format!("line {y}"), Style::new());
+    }
+    screen.flush(&mut term, &caps).unwrap();
+    for y in 0..4 {
+        screen.print(0, y, &format!("line {}", y + 1), Style::new());
+    }
+    let mut out = Vec::new();
+    screen.flush(&mut out, &caps).unwrap();
+    assert_eq!(String::from_utf8_lossy(&out), "\x1b[S\x1b[4;1Hline 4");
+    term.feed(&out);
+    assert_eq!(term.text(), "line 1\nline 2\nline 3\nline 4");
+
+    // Scrolling down, within a region that leaves the last row in place
+    screen.print(0, 0, "new", Style::new());
+    screen.print(3, 0, "   ", Style::new());
+    screen.print(0, 1, "line 1", Style::new());
+    screen.print(0, 2, "line 2", Style::new());
+    let mut out = Vec::new();
+    screen.flush(&mut out, &caps).unwrap();
+    assert_eq!(String::from_utf8_lossy(&out), "\x1b[1;3r\x1b[T\x1b[rnew");
+    term.feed(&out);
+    assert_eq!(term.text(), "new\nline 1\nline 2\nline 4");
+
+    // Without erasing, the first flush prints every cell, and blanks are printed as spaces
+    let mut caps = caps;
+    caps.erase.in_line = false;
+    caps.erase.in_display = false;
+    let mut screen = Screen::new(6, 2);
+    let mut term = VirtualScreen::new(6, 2);
+    term.feed(b"garbage");
+    screen.print(0, 1, "hi", Style::new());
+    let mut out = Vec::new();
+    screen.flush(&mut out, &caps).unwrap();
+    let out = String::from_utf8_lossy(&out).into_owned();
+    assert!(!out.contains('J') && !out.contains('K'));
+    term.feed(out.as_bytes());
+    assert_eq!(term.text(), "\nhi");
+
+    screen.print(0, 1, "      ", Style::new());
+    let mut out = Vec::new();
+    screen.flush(&mut out, &caps).unwrap();
+    assert!(!String::from_utf8_lossy(&out).contains('K'));
+    term.feed(&out);
+    assert_eq!(term.text(), "");
+
+    // After a failed write, the next flush starts over
+    struct Unflushable;
+    impl io::Write for Unflushable {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+    screen.print(0, 0, "failed", Style::new());
+    assert!(screen.flush(&mut Unflushable, &caps).is_err());
+    screen.flush(&mut term, &caps).unwrap();
+    assert_eq!(term.text(), "failed");
+
+    // Nothing to draw without any columns
+    let mut screen = Screen::new(0, 2);
+    screen.flush(&mut Vec::new(), &caps).unwrap();
+    screen.resize(3, 2);
+    screen.resize(0, 2);
+    screen.flush(&mut Vec::new(), &caps).unwrap();
+}
@@ -1,9 +1,13 @@
 //! Styling available through ANSI escape codes
 
+use crate::capinfo::{StyleCap, UnderlineCap};
+use crate::color::ColorLayer;
 use crate::Color;
+use std::fmt::{Display, Write as _};
+use std::io;
 
 /// Collection of styling information for terminal-based output
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
 pub struct Style {
     /// Color of the text, if provided
     pub foreground: Option<Color>,
@@ -31,7 +35,7 @@ pub struct Style {
 /// dedicated separate type in order to allow users with fancier terminals to have some more fun ✨
 ///
 /// By default, underlines have an unspecified color and an [`UnderlineShape::Straight`].
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
 pub struct UnderlineStyle {
     /// Color of the underline, if specified
     pub color: Option<Color>,
@@ -43,7 +47,7 @@ pub struct UnderlineStyle {
 ///
 /// Most terminal emulators do not support changing the shape of an underline, but some do. By
 /// default, styling will fall back to `Straight` if the shape is unsupported.
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
 pub enum UnderlineShape {
     /// Normal, straight underlines
     ///
@@ -121,3 +125,271 @@ impl Style {
         Style { strikethrough: enabled, ..self }
     }
 }
+
+/// `Style` escape sequences
+impl Style {
+    /// Adds the SGR parameters that set this style, replacing whatever style came before it
+    ///
+    /// If the terminal cannot reset styles, nothing is added; see [`StyleCap::reset_all`].
+    pub(crate) fn push_set_params(&self, caps: &StyleCap, params: &mut SgrParams) {
+        if !caps.reset_all {
+            return;
+        }
+
+        params.push(0);
+        DisplayedStyle::new(self, caps).push_all(params);
+    }
+
+    /// Adds the shortest set of SGR parameters that changes the style from `from` to `to`
+    ///
+    /// Attributes are individually unset where the terminal supports it. If some attribute can't
+    /// be unset (or if it would be shorter anyways), we fall back to resetting everything and
+    /// applying `to` from scratch.
+    pub(crate) fn push_transition_params(
+        from: &Style,
+        to: &Style,
+        caps: &StyleCap,
+        params: &mut SgrParams,
+    ) {
+        if !caps.reset_all {
+            return;
+        }
+
+        let (old, new) = (
+            DisplayedStyle::new(from, caps),
+            DisplayedStyle::new(to, caps),
+        );
+        if old == new {
+            return;
+        }
+
+        let mut reset = SgrParams::new();
+        reset.push(0);
+        new.push_all(&mut reset);
+
+        let mut incremental = SgrParams::new();
+        match old.push_changes(&new, caps, &mut incremental) {
+            Some(()) if incremental.buf.len() <= reset.buf.len() => params.append(&incremental),
+            _ => params.append(&reset),
+        }
+    }
+}
+
+/// The SGR parameters produced by each attribute of a [`Style`], as it would actually be displayed
+/// by the terminal
+///
+/// Attributes that the terminal doesn't support are empty (or `false`), so that two `Style`s that
+/// only differ in unsupported ways are considered equal.
+#[derive(PartialEq)]
+struct DisplayedStyle {
+    bold: bool,
+    faint: bool,
+    italic: bool,
+    underline: SgrParams,
+    underline_color: SgrParams,
+    inverse: bool,
+    strikethrough: bool,
+    foreground: SgrParams,
+    background: SgrParams,
+}
+
+impl DisplayedStyle {
+    fn new(style: &Style, caps: &StyleCap) -> Self {
+        let color_params = |color: Option<Color>, layer| {
+            let mut params = SgrParams::new();
+            if let Some(c) = color {
+                c.push_sgr_params(layer, caps.set_color, &mut params);
+            }
+            params
+        };
+
+        let mut underline = SgrParams::new();
+        let mut underline_color = SgrParams::new();
+        if let Some(u) = style.underline {
+            u.push_params(caps, &mut underline);
+            if matches!(caps.set_underline, UnderlineCap::Fancy(f) if f.kitty) {
+                underline_color = color_params(u.color, ColorLayer::Underline);
+            }
+        }
+
+        DisplayedStyle {
+            bold: style.bold && caps.set_bold,
+            faint: style.faint && caps.set_faint,
+            italic: style.italic && caps.set_italics,
+            underline,
+            underline_color,
+            inverse: style.inverse && caps.set_inverse,
+            strikethrough: style.strikethrough && caps.set_strikethrough,
+            foreground: color_params(style.foreground, ColorLayer::Foreground),
+            background: color_params(style.background, ColorLayer::Background),
+        }
+    }
+
+    /// Adds the SGR parameters that enable each attribute present, without disabling any that
+    /// aren't
+    fn push_all(&self, params: &mut SgrParams) {
+        if self.bold {
+            params.push(1);
+        }
+        if self.faint {
+            params.push(2);
+        }
+        if self.italic {
+            params.push(3);
+        }
+        params.append(&self.underline);
+        params.append(&self.underline_color);
+        if self.inverse {
+            params.push(7);
+        }
+        if self.strikethrough {
+            params.push(9);
+        }
+        params.append(&self.foreground);
+        params.append(&self.background);
+    }
+
+    /// Adds the SGR parameters that change from this style to `new`, without resetting everything
+    ///
+    /// Returns `None` if some attribute needs to be unset and the terminal can't do that.
+    fn push_changes(&self, new: &Self, caps: &StyleCap, params: &mut SgrParams) -> Option<()> {
+        // Bold and faint can only be unset together
+        if (self.bold && !new.bold) || (self.faint && !new.faint) {
+            caps.unset_bold_faint.then_some(())?;
+            params.push(22);
+            if new.bold {
+                params.push(1);
+            }
+            if new.faint {
+                params.push(2);
+            }
+        } else {
+            if new.bold && !self.bold {
+                params.push(1);
+            }
+            if new.faint && !self.faint {
+                params.push(2);
+            }
+        }
+
+        if self.italic != new.italic {
+            match new.italic {
+                true => params.push(3),
+                false => caps.unset_italics.then(|| params.push(23))?,
+            }
+        }
+
+        if self.underline != new.underline {
+            // Kitty-style underlines (`4` and `4:<N>`) replace each other, but double underlines
+            // from `ESC[21m` may not be cleared by setting a single underline
+            let replaces =
+                self.underline.buf.starts_with('4') && new.underline.buf.starts_with('4');
+            if new.underline.buf.is_empty() || !replaces {
+                caps.unset_underline.then(|| params.push(24))?;
+            }
+            params.append(&new.underline);
+        }
+
+        if self.underline_color != new.underline_color {
+            match new.underline_color.buf.is_empty() {
+                // Underline colors are only ever set with kitty-style underlines, which also
+                // provide a way to reset them
+                true => params.push(59),
+                false => params.append(&new.underline_color),
+            }
+        }
+
+        if self.inverse != new.inverse {
+            match new.inverse {
+                true => params.push(7),
+                false => caps.unset_inverse.then(|| params.push(27))?,
+            }
+        }
+
+        if self.strikethrough != new.strikethrough {
+            match new.strikethrough {
+                true => params.push(9),
+                false => caps.unset_strikethrough.then(|| params.push(29))?,
+            }
+        }
+
+        for (old, new, unset) in [
+            (&self.foreground, &new.foreground, 39),
+            (&self.background, &new.background, 49),
+        ] {
+            if old != new {
+                match new.buf.is_empty() {
+                    true => caps.unset_color.then(|| params.push(unset))?,
+                    false => params.append(new),
+                }
+            }
+        }
+
+        Some(())
+    }
+}
+
+impl UnderlineStyle {
+    /// Adds the SGR parameters for the shape of this underline, falling back to plain underlines
+    /// if the shape isn't supported
+    fn push_params(&self, caps: &StyleCap, params: &mut SgrParams) {
+        let fancy = match caps.set_underline {
+            UnderlineCap::None => return,
+            UnderlineCap::Basic => {
+                params.push(4);
+                return;
+            }
+            UnderlineCap::Fancy(fancy) => fancy,
+        };
+
+        match self.style {
+            UnderlineShape::Straight => params.push(4),
+            UnderlineShape::Double if fancy.kitty => params.push("4:2"),
+            UnderlineShape::Double if fancy.double => params.push(21),
+            UnderlineShape::Curly if fancy.kitty => params.push("4:3"),
+            UnderlineShape::Dotted if fancy.kitty => params.push("4:4"),
+            UnderlineShape::Dashed if fancy.kitty => params.push("4:5"),
+            _ => params.push(4),
+        }
+    }
+}
+
+/// Builder for the parameters of a single SGR ("Select Graphic Rendition") escape sequence
+///
+/// Parameters are separated by semicolons and written out together as `ESC[<PARAMS>m`.
+#[derive(PartialEq)]
+pub(crate) struct SgrParams {
+    buf: String,
+}
+
+impl SgrParams {
+    pub(crate) fn new() -> Self {
+        SgrParams { buf: String::new() }
+    }
+
+    /// Adds a single parameter (which may itself contain colon-separated sub-parameters)
+    pub(crate) fn push(&mut self, param: impl Display) {
+        if !self.buf.is_empty() {
+            self.buf.push(';');
+        }
+
+        // Writing to a `String` cannot fail
+        let _ = write!(self.buf, "{param}");
+    }
+
+    /// Adds all of the parameters from `other`
+    fn append(&mut self, other: &SgrParams) {
+        if !other.buf.is_empty() {
+            self.push(&other.buf);
+        }
+    }
+
+    /// Writes the full escape sequence, or nothing if no parameters were added
+    pub(crate) fn write_to<W: io::Write + ?Sized>(&self, out: &mut W) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        write!(out, "\x1b[{}m", self.buf)
+    }
+}
@@ -0,0 +1,93 @@
+//! Synchronized updates, for batching together changes to the screen
+
+use crate::capinfo::TermCap;
+use crate::{Command, Mode, WriteCommandError};
+use std::io;
+
+/// Guard that wraps a batch of output in a synchronized update
+///
+/// While a synchronized update is in progress, supporting terminals hold off on redrawing the
+/// screen so that the entire batch appears at once, instead of tearing partway through. For
+/// terminals that don't support it (see [`ModeCap::synchronized_output`]), the guard just passes
+/// output through unchanged.
+///
+/// Output can be written either with [`command`], or directly through the `io::Write`
+/// implementation. The update is ended by calling [`end`] or dropping the guard -- although
+/// errors from writing the end marker can only be observed with the former.
+///
+/// [`ModeCap::synchronized_output`]: crate::capinfo::ModeCap::synchronized_output
+/// [`command`]: Self::command
+/// [`end`]: Self::end
+pub struct SyncedUpdate<'a, W: io::Write + ?Sized> {
+    out: &'a mut W,
+    caps: &'a TermCap,
+    ended: bool,
+}
+
+impl<'a, W: io::Write + ?Sized> SyncedUpdate<'a, W> {
+    /// Begins a synchronized update, if the terminal supports it
+    pub fn begin(out: &'a mut W, caps: &'a TermCap) -> Result<Self, WriteCommandError> {
+        let mut this = SyncedUpdate { out, caps, ended: false };
+        this.write_marker(true)?;
+        Ok(this)
+    }
+
+    /// Writes the command as part of the update
+    pub fn command(&mut self, cmd: &Command) -> Result<(), WriteCommandError> {
+        cmd.write_to(self.out, self.caps)
+    }
+
+    /// Ends the synchronized update, if the terminal supports it
+    pub fn end(mut self) -> Result<(), WriteCommandError> {
+        self.ended = true;
+        self.write_marker(false)
+    }
+
+    fn write_marker(&mut self, enabled: bool) -> Result<(), WriteCommandError> {
+        if !self.caps.mode.synchronized_output {
+            return Ok(());
+        }
+
+        let cmd = Command::SetMode { mode: Mode::SynchronizedOutput, enabled };
+        cmd.write_to(self.out, self.caps)
+    }
+}
+
+impl<W: io::Write + ?Sized> io::Write for SyncedUpdate<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: io::Write + ?Sized> Drop for SyncedUpdate<'_, W> {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.write_marker(false);
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_synced_update() {
+    use crate::CursorCommand;
+    use std::io::Write;
+
+    let run = |terminal| {
+        let caps = crate::capinfo::test_caps(terminal);
+        let mut out = Vec::new();
+        let mut update = SyncedUpdate::begin(&mut out, &caps).unwrap();
+        let move_down = Command::Cursor(CursorCommand::MoveTo { x: None, y: Some(1) });
+        update.command(&move_down).unwrap();
+        update.write_all(b"foo").unwrap();
+        drop(update);
+        String::from_utf8(out).unwrap()
+    };
+
+    assert_eq!(run("kitty"), "\x1b[?2026h\x1b[2dfoo\x1b[?2026l");
+    assert_eq!(run("xterm"), "\x1b[2dfoo");
+}
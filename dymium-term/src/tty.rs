@@ -0,0 +1,487 @@
+//! Low-level interaction with the tty device, via `libc`
+
+use std::cell::UnsafeCell;
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::time::Instant;
+
+/// Guard that puts the tty into raw (or cbreak) mode, restoring its original settings when
+/// dropped
+///
+/// Beyond restoring the settings on drop, the guard also takes care of a couple other cases that
+/// would otherwise leave the user's shell unusable:
+///
+/// * **Panics:** A panic hook is installed (once, wrapping any existing hook) that restores the
+///   settings *before* the panic message is printed.
+/// * **Suspending:** While the guard is active, `SIGTSTP` restores the original settings before
+///   the process is stopped, and `SIGCONT` re-enables raw mode afterwards. In raw mode, `Ctrl-Z`
+///   arrives as input instead of a signal; use [`suspend`] to handle it.
+///
+/// Only one `RawModeGuard` can be active at a time.
+///
+/// [`suspend`]: Self::suspend
+pub struct RawModeGuard {
+    // The signal handlers and panic hook refer to global state, so this is neither `Send` nor
+    // `Sync`: dropping the guard from another thread would be surprising at best.
+    _marker: PhantomData<*const ()>,
+}
+
+/// The mode that a [`RawModeGuard`] puts the tty into
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputMode {
+    /// Raw mode: input is available byte-by-byte, without echoing, and with no special handling
+    /// of control characters (e.g., `Ctrl-C` arrives as input instead of sending `SIGINT`).
+    /// Output processing is also disabled, so newlines don't include a carriage return.
+    Raw,
+    /// Cbreak mode: input is available byte-by-byte, without echoing, but signal-generating
+    /// characters (like `Ctrl-C`) and output processing behave as normal
+    Cbreak,
+}
+
+/// State of the active `RawModeGuard`, shared with the signal handlers and panic hook
+struct GuardState {
+    fd: RawFd,
+    original: libc::termios,
+    modified: libc::termios,
+    prev_sigtstp: libc::sigaction,
+    prev_sigcont: libc::sigaction,
+}
+
+/// Storage for the [`GuardState`], which can only be safely accessed while `ACTIVE` is true
+///
+/// We can't use a `Mutex` here because the state is accessed from signal handlers. Instead, the
+/// state is only written while `ACTIVE` is false, which is guaranteed by the `compare_exchange`
+/// in [`RawModeGuard::enable`].
+struct StateCell(UnsafeCell<Option<GuardState>>);
+
+// SAFETY: see the documentation on `StateCell`
+unsafe impl Sync for StateCell {}
+
+static STATE: StateCell = StateCell(UnsafeCell::new(None));
+/// True while a `RawModeGuard` exists -- or, briefly, while one is being created
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// True if the state has been written and the tty is currently in raw mode
+static IN_RAW_MODE: AtomicBool = AtomicBool::new(false);
+/// Set by the `SIGCONT` handler, and reset by [`RawModeGuard::take_resumed`]
+static RESUMED: AtomicBool = AtomicBool::new(false);
+
+impl RawModeGuard {
+    /// Puts the tty into the given mode, returning a guard that restores the original settings
+    ///
+    /// Returns an error with kind `AlreadyExists` if another `RawModeGuard` is already active.
+    pub fn enable<T: AsRawFd>(tty: &T, mode: InputMode) -> io::Result<Self> {
+        if ACTIVE
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            let msg = "another RawModeGuard is already active";
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+        }
+
+        match Self::enable_inner(tty.as_raw_fd(), mode) {
+            Ok(()) => Ok(RawModeGuard { _marker: PhantomData }),
+            Err(e) => {
+                ACTIVE.store(false, Ordering::Release);
+                Err(e)
+            }
+        }
+    }
+
+    fn enable_inner(fd: RawFd, mode: InputMode) -> io::Result<()> {
+        static INSTALL_PANIC_HOOK: Once = Once::new();
+        INSTALL_PANIC_HOOK.call_once(|| {
+            let prev = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                restore_original();
+                prev(info);
+            }));
+        });
+
+        let original = get_termios(fd)?;
+        let mut modified = original;
+        match mode {
+            // SAFETY: `modified` is a valid, initialized `termios`
+            InputMode::Raw => unsafe { libc::cfmakeraw(&mut modified) },
+            InputMode::Cbreak => modified.c_lflag &= !(libc::ICANON | libc::ECHO),
+        }
+        modified.c_cc[libc::VMIN] = 1;
+        modified.c_cc[libc::VTIME] = 0;
+
+        // The state is filled in before the signal handlers are installed, so that it's never
+        // missing while they are. The previous actions are set as each handler is installed.
+        //
+        // SAFETY: `ACTIVE` is true and `IN_RAW_MODE` is false, so nothing else is accessing the
+        // state -- the signal handlers and panic hook check `IN_RAW_MODE` first. `sigaction` is
+        // plain data, so zeroed values are valid.
+        let state = unsafe {
+            (*STATE.0.get()).insert(GuardState {
+                fd,
+                original,
+                modified,
+                prev_sigtstp: std::mem::zeroed(),
+                prev_sigcont: std::mem::zeroed(),
+            })
+        };
+
+        if let Err(e) = Self::install(state) {
+            // SAFETY: as above
+            unsafe { *STATE.0.get() = None };
+            return Err(e);
+        }
+
+        IN_RAW_MODE.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Installs the signal handlers and applies the modified settings, undoing everything on
+    /// failure
+    fn install(state: &mut GuardState) -> io::Result<()> {
+        state.prev_sigtstp = set_signal_handler(
+            libc::SIGTSTP,
+            handle_sigtstp as *const () as libc::sighandler_t,
+        )?;
+        state.prev_sigcont = match set_signal_handler(
+            libc::SIGCONT,
+            handle_sigcont as *const () as libc::sighandler_t,
+        ) {
+            Ok(prev) => prev,
+            Err(e) => {
+                restore_signal_handler(libc::SIGTSTP, &state.prev_sigtstp);
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = set_termios(state.fd, &state.modified) {
+            restore_signal_handler(libc::SIGTSTP, &state.prev_sigtstp);
+            restore_signal_handler(libc::SIGCONT, &state.prev_sigcont);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Suspends the process, as if `Ctrl-Z` had been pressed outside of raw mode
+    ///
+    /// The original tty settings are restored while the process is stopped, and raw mode is
+    /// re-enabled when it continues. This method returns once the process has continued; the
+    /// screen will typically need to be redrawn afterwards.
+    pub fn suspend(&self) -> io::Result<()> {
+        // SAFETY: `raise` is always safe to call
+        match unsafe { libc::raise(libc::SIGTSTP) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Returns whether the process was continued after being stopped since the last call to this
+    /// method
+    ///
+    /// If this returns true, the contents of the screen may have been changed by another program
+    /// in the meantime, so it should be fully redrawn.
+    pub fn take_resumed(&self) -> bool {
+        RESUMED.swap(false, Ordering::AcqRel)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        restore_original();
+        IN_RAW_MODE.store(false, Ordering::Release);
+
+        // SAFETY: `IN_RAW_MODE` is false, so the signal handlers and panic hook won't access the
+        // state, and `ACTIVE` is still true, so no other guard is being created.
+        if let Some(state) = unsafe { (*STATE.0.get()).take() } {
+            restore_signal_handler(libc::SIGTSTP, &state.prev_sigtstp);
+            restore_signal_handler(libc::SIGCONT, &state.prev_sigcont);
+        }
+
+        ACTIVE.store(false, Ordering::Release);
+    }
+}
+
+/// Returns the state of the active guard, if the tty is in raw mode
+///
+/// This is async-signal-safe.
+fn raw_mode_state() -> Option<&'static GuardState> {
+    match IN_RAW_MODE.load(Ordering::Acquire) {
+        // SAFETY: the state is never written while `IN_RAW_MODE` is true
+        true => unsafe { (*STATE.0.get()).as_ref() },
+        false => None,
+    }
+}
+
+/// Restores the original tty settings of the active guard, if there is one
+fn restore_original() {
+    if let Some(state) = raw_mode_state() {
+        // SAFETY: `tcsetattr` is async-signal-safe, and `state.original` is a valid pointer
+        unsafe { libc::tcsetattr(state.fd, libc::TCSADRAIN, &state.original) };
+    }
+}
+
+extern "C" fn handle_sigtstp(_: libc::c_int) {
+    restore_original();
+
+    // Stop the process with the default action. `SIGTSTP` is blocked while this handler runs, so
+    // the signal will be delivered once we return.
+    //
+    // SAFETY: `signal` and `raise` are async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGTSTP, libc::SIG_DFL);
+        libc::raise(libc::SIGTSTP);
+    }
+}
+
+extern "C" fn handle_sigcont(_: libc::c_int) {
+    if let Some(state) = raw_mode_state() {
+        // SAFETY: `tcsetattr` and `signal` are async-signal-safe
+        unsafe {
+            libc::tcsetattr(state.fd, libc::TCSADRAIN, &state.modified);
+            libc::signal(
+                libc::SIGTSTP,
+                handle_sigtstp as *const () as libc::sighandler_t,
+            );
+        }
+        RESUMED.store(true, Ordering::Release);
+    }
+}
+
+/// Sets the handler for the signal, returning the previous action
+fn set_signal_handler(
+    signal: libc::c_int,
+    handler: libc::sighandler_t,
+) -> io::Result<libc::sigaction> {
+    // SAFETY: `sigaction` is plain data, so zeroed values are valid. `sigemptyset` and `sigaction`
+    // are given valid pointers.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut prev: libc::sigaction = std::mem::zeroed();
+        match libc::sigaction(signal, &action, &mut prev) {
+            0 => Ok(prev),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Restores a signal action previously returned by [`set_signal_handler`]
+fn restore_signal_handler(signal: libc::c_int, prev: &libc::sigaction) {
+    // SAFETY: `prev` is a valid pointer, and a null pointer is allowed for the old action
+    unsafe { libc::sigaction(signal, prev, std::ptr::null_mut()) };
+}
+
+/// Guard that disables canonical mode and echoing on the tty, restoring the original settings on
+/// drop
+///
+/// This is the minimum required to read replies to queries: without it, the terminal's reply
+/// would be buffered until the next newline, and echoed back to the screen.
+pub(crate) struct NoEchoGuard {
+    fd: RawFd,
+    original: libc::termios,
+}
+
+impl NoEchoGuard {
+    pub(crate) fn new(fd: RawFd) -> io::Result<Self> {
+        let original = get_termios(fd)?;
+        let mut termios = original;
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        set_termios(fd, &termios)?;
+
+        Ok(NoEchoGuard { fd, original })
+    }
+}
+
+impl Drop for NoEchoGuard {
+    fn drop(&mut self) {
+        let _ = set_termios(self.fd, &self.original);
+    }
+}
+
+/// Returns the current termios settings for the file descriptor
+pub(crate) fn get_termios(fd: RawFd) -> io::Result<libc::termios> {
+    let mut termios = std::mem::MaybeUninit::uninit();
+    // SAFETY: `tcgetattr` initializes the `termios` if it succeeds, which we check.
+    unsafe {
+        if libc::tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(termios.assume_init())
+    }
+}
+
+/// Sets the termios settings for the file descriptor, after all pending output is written
+pub(crate) fn set_termios(fd: RawFd, termios: &libc::termios) -> io::Result<()> {
+    // SAFETY: `termios` is a valid pointer for the duration of the call
+    match unsafe { libc::tcsetattr(fd, libc::TCSADRAIN, termios) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Reads from the file descriptor into `buf` until `done` returns true for the accumulated bytes,
+/// or the deadline passes
+///
+/// Returns whether `done` returned true.
+pub(crate) fn read_until(
+    fd: RawFd,
+    buf: &mut Vec<u8>,
+    deadline: Instant,
+    mut done: impl FnMut(&[u8]) -> bool,
+) -> io::Result<bool> {
+    let mut chunk = [0_u8; 256];
+
+    while !done(buf) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+
+        let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        let timeout_ms = remaining.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+        // SAFETY: `pollfd` is a valid pointer to exactly one `pollfd` for the duration of the call
+        match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+            0 => return Ok(false),
+            n if n < 0 => {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(err),
+                }
+            }
+            _ => (),
+        }
+
+        // SAFETY: `chunk` is valid for writes of up to `chunk.len()` bytes
+        let n = unsafe { libc::read(fd, chunk.as_mut_ptr().cast(), chunk.len()) };
+        match n {
+            0 => return Ok(false),
+            n if n < 0 => {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => continue,
+                    _ => return Err(err),
+                }
+            }
+            n => buf.extend_from_slice(&chunk[..n as usize]),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Returns the position just past the end of the first primary device attributes (DA1) reply in
+/// the bytes, if there is one
+///
+/// The reply has the form `ESC[?<PARAMS>c`. Because every terminal replies to DA1, it's useful as
+/// a sentinel after other queries: once its reply has arrived, any replies to earlier queries
+/// should have arrived as well.
+pub(crate) fn find_da1_reply(bytes: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    while let Some(i) = find(&bytes[start..], b"\x1b[?") {
+        let params_start = start + i + 3;
+        let len = bytes[params_start..]
+            .iter()
+            .position(|&b| !matches!(b, b'0'..=b'9' | b';'));
+        match len {
+            Some(len) if bytes[params_start + len] == b'c' => return Some(params_start + len + 1),
+            _ => start = params_start,
+        }
+    }
+    None
+}
+
+/// Returns the position of the first occurence of `needle` in `haystack`
+pub(crate) fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+#[test]
+fn test_find_da1_reply() {
+    assert_eq!(find_da1_reply(b"\x1b[?62;22c"), Some(9));
+    assert_eq!(find_da1_reply(b"\x1b]11;0\x07\x1b[?1;2cxyz"), Some(14));
+    assert_eq!(find_da1_reply(b"\x1b[?1;2"), None);
+    assert_eq!(find_da1_reply(b"\x1b[?1u\x1b[?6c"), Some(10));
+}
+
+#[cfg(test)]
+#[test]
+fn test_raw_mode_guard() {
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+    use std::ptr;
+
+    let (mut master, mut slave) = (0, 0);
+    // SAFETY: the file descriptor pointers are valid, and the rest are allowed to be null
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    assert_eq!(result, 0);
+    // SAFETY: `openpty` returned new file descriptors, which nothing else owns
+    let (_master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+    let fd = slave.as_raw_fd();
+    let original = get_termios(fd).unwrap();
+    let lflag = || get_termios(fd).unwrap().c_lflag;
+
+    let guard = RawModeGuard::enable(&slave, InputMode::Cbreak).unwrap();
+    let cbreak = get_termios(fd).unwrap();
+    assert_eq!(cbreak.c_lflag & (libc::ICANON | libc::ECHO), 0);
+    assert_ne!(cbreak.c_lflag & libc::ISIG, 0);
+    match RawModeGuard::enable(&slave, InputMode::Raw) {
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+        Ok(_) => panic!("enabled a second RawModeGuard"),
+    }
+    drop(guard);
+    assert_eq!(lflag(), original.c_lflag);
+
+    let guard = RawModeGuard::enable(&slave, InputMode::Raw).unwrap();
+    let raw = get_termios(fd).unwrap();
+    assert_eq!(raw.c_lflag & (libc::ICANON | libc::ECHO | libc::ISIG), 0);
+    assert_eq!(raw.c_oflag & libc::OPOST, 0);
+
+    // Suspending stops the whole process, so it's done in a child. The child is put in its own
+    // process group, so that it isn't orphaned -- stop signals are ignored in orphaned groups.
+    //
+    // SAFETY: other test threads may be running, so the child only makes async-signal-safe calls
+    // (and doesn't allocate) before exiting.
+    match unsafe { libc::fork() } {
+        0 => unsafe {
+            libc::setpgid(0, 0);
+            let resumed = guard.suspend().is_ok() && guard.take_resumed();
+            let reapplied = get_termios(fd).is_ok_and(|t| t.c_lflag == raw.c_lflag);
+            libc::_exit(if resumed && reapplied { 0 } else { 1 });
+        },
+        pid => {
+            assert!(pid > 0);
+            let mut status = 0;
+            // SAFETY: `status` is a valid pointer, and `pid` is our child
+            unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) };
+            assert!(libc::WIFSTOPPED(status));
+            assert_eq!(lflag(), original.c_lflag);
+
+            // SAFETY: as above
+            unsafe {
+                libc::kill(pid, libc::SIGCONT);
+                libc::waitpid(pid, &mut status, 0);
+            }
+            assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+            assert_eq!(lflag(), raw.c_lflag);
+        }
+    }
+
+    drop(guard);
+    assert_eq!(lflag(), original.c_lflag);
+}
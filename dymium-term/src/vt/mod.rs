@@ -0,0 +1,533 @@
+//! Parsing the output *to* a terminal, for implementing a terminal emulator
+//!
+//! [`Parser`] implements the state machine for DEC's VT500-series terminals, as described by Paul
+//! Williams at <https://vt100.net/emu/dec_ansi_parser>. It splits a stream of bytes into printable
+//! characters, control characters, and escape sequences, and passes each of them to a [`Perform`]
+//! implementation to act on.
+//!
+//! A few changes are made from the original state machine, to match modern terminals:
+//!
+//! * Input is UTF-8, so bytes `0x80..=0xFF` never act as C1 control characters. Printable
+//!   characters are decoded from UTF-8, with any malformed input replaced by `U+FFFD REPLACEMENT
+//!   CHARACTER`.
+//! * Colons are allowed in CSI and DCS parameters, separating sub-parameters (as in
+//!   `ESC[38:2::255:0:0m`).
+//! * OSC strings may be terminated by `BEL`, as well as `ESC\`.
+//!
+//! [`VirtualScreen`] builds on the parser to keep track of what a terminal would display.
+
+mod screen;
+
+pub use screen::VirtualScreen;
+
+/// The maximum number of parameters (including sub-parameters) in a CSI or DCS sequence
+///
+/// Sequences with more parameters than this are still dispatched, but with `ignore` set.
+const MAX_PARAMS: usize = 32;
+/// The maximum number of intermediate bytes in an escape sequence
+///
+/// Sequences with more intermediates than this are still dispatched, but with `ignore` set.
+const MAX_INTERMEDIATES: usize = 2;
+/// The maximum number of parameters in an OSC string
+///
+/// Any further `;` are treated as part of the last parameter.
+const MAX_OSC_PARAMS: usize = 16;
+/// The maximum length of an OSC string, in bytes
+///
+/// Longer strings are dropped entirely, instead of being dispatched.
+const MAX_OSC_LEN: usize = 64 * 1024;
+
+/// Handler for the actions produced by a [`Parser`]
+///
+/// All methods have default implementations that do nothing, so implementors only need to handle
+/// what they care about.
+#[allow(unused_variables)]
+pub trait Perform {
+    /// Displays a character
+    fn print(&mut self, c: char) {}
+
+    /// Executes a C0 control character, like `\n` or `BEL`
+    fn execute(&mut self, byte: u8) {}
+
+    /// Dispatches a CSI sequence, `ESC[<PARAMS><INTERMEDIATES><ACTION>`
+    ///
+    /// Private markers (`<`, `=`, `>`, or `?`) at the start of the parameters are included with the
+    /// intermediates, so e.g. `ESC[?25h` has intermediates `?` and action `h`. If `ignore` is
+    /// true, there were too many parameters or intermediates to store, and the sequence should
+    /// probably be ignored.
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: u8) {}
+
+    /// Dispatches an escape sequence that isn't CSI, OSC, or DCS -- e.g., `ESC 7` or `ESC(B`
+    ///
+    /// The string terminator `ESC\` is also dispatched through here, after the string it ends.
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {}
+
+    /// Dispatches an OSC string, `ESC]<PARAMS>` (terminated by `ESC\` or `BEL`), with its
+    /// semicolon-separated parameters
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {}
+
+    /// Starts a DCS string, `ESC P<PARAMS><INTERMEDIATES><ACTION>`
+    ///
+    /// The data in the string is passed to [`put`](Self::put), until it's ended by
+    /// [`unhook`](Self::unhook).
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: u8) {}
+
+    /// Passes a byte from the data of the current DCS string
+    fn put(&mut self, byte: u8) {}
+
+    /// Ends the current DCS string
+    fn unhook(&mut self) {}
+}
+
+/// Parameters of a CSI or DCS sequence
+///
+/// Each parameter is a list of its colon-separated sub-parameters -- usually, just one. Missing
+/// values are given as zero, so `ESC[;5H` has parameters `[[0], [5]]`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Params {
+    values: Vec<u16>,
+    /// The end of each parameter in `values`
+    ends: Vec<usize>,
+}
+
+/// Parser for the output to a terminal, dispatching to a [`Perform`]
+///
+/// See the [module-level documentation](self) for more.
+#[derive(Debug, Default)]
+pub struct Parser {
+    state: State,
+
+    intermediates: [u8; MAX_INTERMEDIATES],
+    intermediates_len: usize,
+    /// Set if there were too many intermediates or parameters, or the OSC string was too long
+    ignoring: bool,
+
+    params: Params,
+    /// The value of the parameter currently being parsed
+    param: u16,
+    /// True if any parameter bytes have been seen in the current sequence
+    has_params: bool,
+
+    osc_raw: Vec<u8>,
+    /// The end of each OSC parameter in `osc_raw`, excluding the last
+    osc_ends: Vec<usize>,
+
+    utf8: [u8; 4],
+    utf8_len: usize,
+    utf8_needed: usize,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+enum State {
+    #[default]
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    DcsPassthrough,
+    DcsIgnore,
+    OscString,
+    SosPmApcString,
+}
+
+impl Params {
+    /// Returns the number of parameters
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// Returns whether there are no parameters
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Returns the `i`th parameter, with its sub-parameters
+    pub fn get(&self, i: usize) -> Option<&[u16]> {
+        let start = match i {
+            0 => 0,
+            _ => *self.ends.get(i - 1)?,
+        };
+        Some(&self.values[start..*self.ends.get(i)?])
+    }
+
+    /// Returns an iterator over the parameters, each with its sub-parameters
+    pub fn iter(&self) -> impl '_ + Iterator<Item = &[u16]> {
+        (0..self.len()).filter_map(|i| self.get(i))
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.ends.clear();
+    }
+}
+
+impl Parser {
+    /// Creates a new `Parser`, in the initial "ground" state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the bytes, passing the resulting actions to the performer
+    ///
+    /// Sequences may be split across calls; the parser keeps track of where it left off.
+    pub fn advance<P: Perform>(&mut self, performer: &mut P, bytes: &[u8]) {
+        for &b in bytes {
+            self.advance_byte(performer, b);
+        }
+    }
+
+    fn advance_byte<P: Perform>(&mut self, p: &mut P, byte: u8) {
+        if self.utf8_len != 0 {
+            if byte & 0xc0 == 0x80 {
+                return self.continue_utf8(p, byte);
+            }
+            self.utf8_len = 0;
+            p.print('\u{FFFD}');
+        }
+
+        // Transitions from anywhere
+        match byte {
+            0x18 | 0x1a => {
+                self.exit_state(p);
+                p.execute(byte);
+                self.state = State::Ground;
+                return;
+            }
+            0x1b => {
+                self.exit_state(p);
+                self.clear();
+                self.state = State::Escape;
+                return;
+            }
+            0x80.. => {
+                match self.state {
+                    State::Ground => self.start_utf8(p, byte),
+                    State::OscString => self.osc_put(byte),
+                    State::DcsPassthrough => p.put(byte),
+                    _ => (),
+                }
+                return;
+            }
+            _ => (),
+        }
+
+        let is_c0 = byte < 0x20;
+        match self.state {
+            State::Ground => match byte {
+                _ if is_c0 => p.execute(byte),
+                0x7f => (),
+                _ => p.print(byte as char),
+            },
+            State::Escape => match byte {
+                _ if is_c0 => p.execute(byte),
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::EscapeIntermediate;
+                }
+                b'[' => self.state = State::CsiEntry,
+                b']' => {
+                    self.osc_raw.clear();
+                    self.osc_ends.clear();
+                    self.state = State::OscString;
+                }
+                b'P' => self.state = State::DcsEntry,
+                b'X' | b'^' | b'_' => self.state = State::SosPmApcString,
+                0x7f => (),
+                _ => self.esc_dispatch(p, byte),
+            },
+            State::EscapeIntermediate => match byte {
+                _ if is_c0 => p.execute(byte),
+                0x20..=0x2f => self.collect(byte),
+                0x7f => (),
+                _ => self.esc_dispatch(p, byte),
+            },
+            State::CsiEntry | State::CsiParam | State::CsiIntermediate => match byte {
+                _ if is_c0 => p.execute(byte),
+                0x7f => (),
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::CsiIntermediate;
+                }
+                0x30..=0x3b if self.state != State::CsiIntermediate => {
+                    self.param_byte(byte);
+                    self.state = State::CsiParam;
+                }
+                0x3c..=0x3f if self.state == State::CsiEntry => {
+                    self.collect(byte);
+                    self.state = State::CsiParam;
+                }
+                0x30..=0x3f => self.state = State::CsiIgnore,
+                _ => {
+                    self.finish_params();
+                    let intermediates = &self.intermediates[..self.intermediates_len];
+                    p.csi_dispatch(&self.params, intermediates, self.ignoring, byte);
+                    self.state = State::Ground;
+                }
+            },
+            State::CsiIgnore => match byte {
+                _ if is_c0 => p.execute(byte),
+                0x40..=0x7e => self.state = State::Ground,
+                _ => (),
+            },
+            State::DcsEntry | State::DcsParam | State::DcsIntermediate => match byte {
+                _ if is_c0 => (),
+                0x7f => (),
+                0x20..=0x2f => {
+                    self.collect(byte);
+                    self.state = State::DcsIntermediate;
+                }
+                0x30..=0x3b if self.state != State::DcsIntermediate => {
+                    self.param_byte(byte);
+                    self.state = State::DcsParam;
+                }
+                0x3c..=0x3f if self.state == State::DcsEntry => {
+                    self.collect(byte);
+                    self.state = State::DcsParam;
+                }
+                0x30..=0x3f => self.state = State::DcsIgnore,
+                _ => {
+                    self.finish_params();
+                    let intermediates = &self.intermediates[..self.intermediates_len];
+                    p.hook(&self.params, intermediates, self.ignoring, byte);
+                    self.state = State::DcsPassthrough;
+                }
+            },
+            State::DcsPassthrough => match byte {
+                0x7f => (),
+                _ => p.put(byte),
+            },
+            State::OscString => match byte {
+                0x07 => {
+                    self.osc_dispatch(p, true);
+                    self.state = State::Ground;
+                }
+                b';' if self.osc_ends.len() < MAX_OSC_PARAMS - 1 => {
+                    self.osc_ends.push(self.osc_raw.len());
+                }
+                _ if is_c0 => (),
+                _ => self.osc_put(byte),
+            },
+            State::DcsIgnore | State::SosPmApcString => (),
+        }
+    }
+
+    /// Performs the exit action for the current state, when it's left because of `ESC`, `CAN`, or
+    /// `SUB`
+    fn exit_state<P: Perform>(&mut self, p: &mut P) {
+        match self.state {
+            State::OscString => self.osc_dispatch(p, false),
+            State::DcsPassthrough => p.unhook(),
+            _ => (),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.intermediates_len = 0;
+        self.ignoring = false;
+        self.params.clear();
+        self.param = 0;
+        self.has_params = false;
+    }
+
+    fn collect(&mut self, byte: u8) {
+        match self.intermediates_len < MAX_INTERMEDIATES {
+            true => {
+                self.intermediates[self.intermediates_len] = byte;
+                self.intermediates_len += 1;
+            }
+            false => self.ignoring = true,
+        }
+    }
+
+    fn param_byte(&mut self, byte: u8) {
+        self.has_params = true;
+        if self.params.values.len() >= MAX_PARAMS {
+            self.ignoring = true;
+            return;
+        }
+
+        match byte {
+            b';' => {
+                self.params.values.push(self.param);
+                self.params.ends.push(self.params.values.len());
+                self.param = 0;
+            }
+            b':' => {
+                self.params.values.push(self.param);
+                self.param = 0;
+            }
+            _ => {
+                let digit = (byte - b'0') as u16;
+                self.param = self.param.saturating_mul(10).saturating_add(digit);
+            }
+        }
+    }
+
+    /// Adds the final parameter, once the end of the sequence is reached
+    fn finish_params(&mut self) {
+        if self.has_params && self.params.values.len() < MAX_PARAMS {
+            self.params.values.push(self.param);
+            self.params.ends.push(self.params.values.len());
+        }
+    }
+
+    fn esc_dispatch<P: Perform>(&mut self, p: &mut P, byte: u8) {
+        let intermediates = &self.intermediates[..self.intermediates_len];
+        p.esc_dispatch(intermediates, self.ignoring, byte);
+        self.state = State::Ground;
+    }
+
+    fn osc_put(&mut self, byte: u8) {
+        match self.osc_raw.len() < MAX_OSC_LEN {
+            true => self.osc_raw.push(byte),
+            false => self.ignoring = true,
+        }
+    }
+
+    fn osc_dispatch<P: Perform>(&mut self, p: &mut P, bell_terminated: bool) {
+        if self.ignoring {
+            return;
+        }
+
+        let mut params = Vec::with_capacity(self.osc_ends.len() + 1);
+        let mut start = 0;
+        for &end in self.osc_ends.iter().chain([self.osc_raw.len()].iter()) {
+            params.push(&self.osc_raw[start..end]);
+            start = end;
+        }
+        p.osc_dispatch(&params, bell_terminated);
+    }
+
+    fn start_utf8<P: Perform>(&mut self, p: &mut P, byte: u8) {
+        self.utf8_needed = match byte {
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => return p.print('\u{FFFD}'),
+        };
+        self.utf8[0] = byte;
+        self.utf8_len = 1;
+    }
+
+    fn continue_utf8<P: Perform>(&mut self, p: &mut P, byte: u8) {
+        self.utf8[self.utf8_len] = byte;
+        self.utf8_len += 1;
+        if self.utf8_len < self.utf8_needed {
+            return;
+        }
+
+        let c = std::str::from_utf8(&self.utf8[..self.utf8_len])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or('\u{FFFD}');
+        self.utf8_len = 0;
+        p.print(c);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_parser() {
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Perform for Log {
+        fn print(&mut self, c: char) {
+            self.0.push(format!("print {c}"));
+        }
+        fn execute(&mut self, byte: u8) {
+            self.0.push(format!("execute {byte:#x}"));
+        }
+        fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, c: u8) {
+            let params: Vec<_> = params.iter().collect();
+            let intermediates = String::from_utf8_lossy(intermediates);
+            let c = c as char;
+            self.0
+                .push(format!("csi {params:?} {intermediates:?} {ignore} {c}"));
+        }
+        fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+            let intermediates = String::from_utf8_lossy(intermediates);
+            self.0
+                .push(format!("esc {intermediates:?} {}", byte as char));
+        }
+        fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+            let params: Vec<_> = params.iter().map(|p| String::from_utf8_lossy(p)).collect();
+            self.0.push(format!("osc {params:?} {bell_terminated}"));
+        }
+        fn hook(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, c: u8) {
+            let params: Vec<_> = params.iter().collect();
+            let intermediates = String::from_utf8_lossy(intermediates);
+            self.0
+                .push(format!("hook {params:?} {intermediates:?} {}", c as char));
+        }
+        fn put(&mut self, byte: u8) {
+            self.0.push(format!("put {}", byte as char));
+        }
+        fn unhook(&mut self) {
+            self.0.push("unhook".to_owned());
+        }
+    }
+
+    let parse = |chunks: &[&[u8]]| {
+        let mut parser = Parser::new();
+        let mut log = Log::default();
+        for chunk in chunks {
+            parser.advance(&mut log, chunk);
+        }
+        log.0
+    };
+
+    assert_eq!(
+        parse(&[
+            b"a\xc3",
+            b"\xa9\r\x1b[1;38:2::",
+            b"255:0:0m\x1b[?25h\x1b[;5H"
+        ]),
+        [
+            "print a",
+            "print é",
+            "execute 0xd",
+            "csi [[1], [38, 2, 0, 255, 0, 0]] \"\" false m",
+            "csi [[25]] \"?\" false h",
+            "csi [[0], [5]] \"\" false H",
+        ]
+    );
+
+    assert_eq!(
+        parse(&[b"\x1b(B\x1b7\x1b]8;;http://x\x1b\\\x1b]2;title\x07"]),
+        [
+            "esc \"(\" B",
+            "esc \"\" 7",
+            "osc [\"8\", \"\", \"http://x\"] false",
+            "esc \"\" \\",
+            "osc [\"2\", \"title\"] true",
+        ]
+    );
+
+    assert_eq!(
+        parse(&[b"\x1bP>|ab\x1b\\\x1b[1$\x18x\xff"]),
+        [
+            "hook [] \">\" |",
+            "put a",
+            "put b",
+            "unhook",
+            "esc \"\" \\",
+            "execute 0x18",
+            "print x",
+            "print \u{FFFD}",
+        ]
+    );
+
+    let mut long = b"\x1b]52;c;".to_vec();
+    long.resize(MAX_OSC_LEN + 8, b'a');
+    assert_eq!(
+        parse(&[&long, b"\x07\x1b]0;x\x07"]),
+        ["osc [\"0\", \"x\"] true"]
+    );
+}
@@ -0,0 +1,714 @@
+//! Headless model of a terminal's screen, driven by [`Parser`]
+
+use super::{Params, Parser, Perform};
+use crate::{Cell, Color, CursorShape, Mode, Style, UnderlineShape, UnderlineStyle};
+use std::io;
+use std::ops::Range;
+
+/// In-memory model of a terminal's screen, updated by interpreting the output written to it
+///
+/// This understands everything that [`Command`] produces -- so, for example, tests can write
+/// commands to a `VirtualScreen` and check the resulting contents, without a real terminal. Bytes
+/// are given to the screen with [`feed`](Self::feed), or through its [`io::Write`]
+/// implementation.
+///
+/// Like a real terminal, no output processing is done: `\n` moves the cursor down *without*
+/// returning to the start of the line. Some simplifications are made, too:
+///
+/// * Every character is assumed to occupy a single cell.
+/// * There's no scrollback; lines scrolled off the top of the screen are discarded.
+/// * Queries are ignored, as are commands that don't affect what's displayed (e.g., hyperlinks and
+///   palette changes).
+/// * Erasing fills cells with the current background color, as most modern terminals do.
+///
+/// [`Command`]: crate::Command
+#[derive(Debug)]
+pub struct VirtualScreen {
+    parser: Parser,
+    state: ScreenState,
+}
+
+/// The part of a [`VirtualScreen`] that's updated by the parser
+#[derive(Debug)]
+struct ScreenState {
+    width: u16,
+    height: u16,
+    /// The rows of the screen that's currently displayed
+    grid: Vec<Vec<Cell>>,
+    /// The rows of the screen that isn't displayed, either primary or alternate
+    other_grid: Vec<Vec<Cell>>,
+
+    x: u16,
+    y: u16,
+    /// True if a character was printed in the last column, so the next one should wrap to the
+    /// next line first
+    wrap_pending: bool,
+    saved_cursor: Option<SavedCursor>,
+
+    /// The style for newly printed characters
+    pen: Style,
+    /// The color for underlines, kept separately because it's preserved when underlines are
+    /// turned off and back on
+    underline_color: Option<Color>,
+
+    scroll_region: Range<u16>,
+    alternate_screen: bool,
+    autowrap: bool,
+    bracketed_paste: bool,
+    focus_events: bool,
+    synchronized_output: bool,
+
+    cursor_visible: bool,
+    cursor_shape: Option<(CursorShape, bool)>,
+
+    title: String,
+    icon_name: String,
+    title_stack: Vec<(String, String)>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct SavedCursor {
+    x: u16,
+    y: u16,
+    pen: Style,
+    underline_color: Option<Color>,
+}
+
+impl VirtualScreen {
+    /// Creates a new, blank `VirtualScreen` with the given size
+    ///
+    /// A width or height of zero is treated as one, since the cursor always needs a cell to be on.
+    pub fn new(width: u16, height: u16) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let blank = vec![vec![Cell::default(); width as usize]; height as usize];
+        let state = ScreenState {
+            width,
+            height,
+            grid: blank.clone(),
+            other_grid: blank,
+            x: 0,
+            y: 0,
+            wrap_pending: false,
+            saved_cursor: None,
+            pen: Style::default(),
+            underline_color: None,
+            scroll_region: 0..height,
+            alternate_screen: false,
+            autowrap: true,
+            bracketed_paste: false,
+            focus_events: false,
+            synchronized_output: false,
+            cursor_visible: true,
+            cursor_shape: None,
+            title: String::new(),
+            icon_name: String::new(),
+            title_stack: Vec::new(),
+        };
+
+        VirtualScreen { parser: Parser::new(), state }
+    }
+
+    /// Interprets the bytes, as if they were written to the terminal
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.parser.advance(&mut self.state, bytes);
+    }
+
+    /// Returns the width of the screen, in cells
+    pub fn width(&self) -> u16 {
+        self.state.width
+    }
+
+    /// Returns the height of the screen, in cells
+    pub fn height(&self) -> u16 {
+        self.state.height
+    }
+
+    /// Returns the cell at the given position, with the top-left corner at (0, 0)
+    pub fn cell(&self, x: u16, y: u16) -> Option<&Cell> {
+        self.state.grid.get(y as usize)?.get(x as usize)
+    }
+
+    /// Returns the text on the given row, without trailing spaces
+    pub fn row_text(&self, y: u16) -> String {
+        let row = self.state.grid.get(y as usize).map_or(&[][..], |r| r);
+        let text: String = row.iter().map(|cell| cell.c).collect();
+        text.trim_end_matches(' ').to_owned()
+    }
+
+    /// Returns the text on the screen, with rows separated by `\n` and trailing spaces and blank
+    /// rows removed
+    pub fn text(&self) -> String {
+        let rows: Vec<_> = (0..self.height()).map(|y| self.row_text(y)).collect();
+        rows.join("\n").trim_end_matches('\n').to_owned()
+    }
+
+    /// Returns the position of the cursor, as `(x, y)`
+    pub fn cursor(&self) -> (u16, u16) {
+        (self.state.x, self.state.y)
+    }
+
+    /// Returns whether the cursor is visible
+    pub fn cursor_visible(&self) -> bool {
+        self.state.cursor_visible
+    }
+
+    /// Returns the shape of the cursor and whether it blinks, or `None` if it's the terminal's
+    /// default
+    pub fn cursor_shape(&self) -> Option<(CursorShape, bool)> {
+        self.state.cursor_shape
+    }
+
+    /// Returns the style that newly printed characters will have
+    pub fn style(&self) -> Style {
+        self.state.pen
+    }
+
+    /// Returns the range of rows that scrolling applies to
+    pub fn scroll_region(&self) -> Range<u16> {
+        self.state.scroll_region.clone()
+    }
+
+    /// Returns whether the mode is enabled
+    pub fn mode(&self, mode: Mode) -> bool {
+        match mode {
+            Mode::AlternateScreen => self.state.alternate_screen,
+            Mode::BracketedPaste => self.state.bracketed_paste,
+            Mode::FocusEvents => self.state.focus_events,
+            Mode::Autowrap => self.state.autowrap,
+            Mode::SynchronizedOutput => self.state.synchronized_output,
+        }
+    }
+
+    /// Returns the window title
+    pub fn title(&self) -> &str {
+        &self.state.title
+    }
+
+    /// Returns the icon name
+    pub fn icon_name(&self) -> &str {
+        &self.state.icon_name
+    }
+}
+
+impl io::Write for VirtualScreen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.feed(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the first value of the `i`th parameter, or `default` if it's missing or zero
+fn param_or(params: &Params, i: usize, default: u16) -> u16 {
+    match params.get(i).map_or(0, |p| p[0]) {
+        0 => default,
+        n => n,
+    }
+}
+
+impl ScreenState {
+    /// Returns a blank cell, as left by erasing
+    fn blank(&self) -> Cell {
+        let style = Style {
+            background: self.pen.background,
+            ..Style::default()
+        };
+        Cell { c: ' ', style }
+    }
+
+    fn blank_row(&self) -> Vec<Cell> {
+        vec![self.blank(); self.width as usize]
+    }
+
+    fn move_to(&mut self, x: u16, y: u16) {
+        self.x = x.min(self.width.saturating_sub(1));
+        self.y = y.min(self.height.saturating_sub(1));
+        self.wrap_pending = false;
+    }
+
+    /// Moves the cursor down a line, scrolling if it's at the bottom of the scroll region
+    fn linefeed(&mut self) {
+        self.wrap_pending = false;
+        if self.y + 1 == self.scroll_region.end {
+            self.scroll_up(1);
+        } else if self.y + 1 < self.height {
+            self.y += 1;
+        }
+    }
+
+    /// Moves the cursor up a line, scrolling if it's at the top of the scroll region
+    fn reverse_linefeed(&mut self) {
+        self.wrap_pending = false;
+        if self.y == self.scroll_region.start {
+            self.scroll_down(1);
+        } else {
+            self.y = self.y.saturating_sub(1);
+        }
+    }
+
+    /// Scrolls the contents of the scroll region up, adding blank lines at the bottom
+    fn scroll_up(&mut self, n: u16) {
+        let Range { start, end } = self.scroll_region.clone();
+        for _ in 0..n.min(end - start) {
+            self.grid.remove(start as usize);
+            self.grid.insert(end as usize - 1, self.blank_row());
+        }
+    }
+
+    /// Scrolls the contents of the scroll region down, adding blank lines at the top
+    fn scroll_down(&mut self, n: u16) {
+        let Range { start, end } = self.scroll_region.clone();
+        for _ in 0..n.min(end - start) {
+            self.grid.remove(end as usize - 1);
+            self.grid.insert(start as usize, self.blank_row());
+        }
+    }
+
+    /// Inserts blank lines at the cursor, pushing the rest of the scroll region down
+    fn insert_lines(&mut self, n: u16) {
+        let Range { start, end } = self.scroll_region.clone();
+        if (start..end).contains(&self.y) {
+            for _ in 0..n.min(end - self.y) {
+                self.grid.remove(end as usize - 1);
+                self.grid.insert(self.y as usize, self.blank_row());
+            }
+            self.move_to(0, self.y);
+        }
+    }
+
+    /// Deletes lines at the cursor, pulling the rest of the scroll region up
+    fn delete_lines(&mut self, n: u16) {
+        let Range { start, end } = self.scroll_region.clone();
+        if (start..end).contains(&self.y) {
+            for _ in 0..n.min(end - self.y) {
+                self.grid.remove(self.y as usize);
+                self.grid.insert(end as usize - 1, self.blank_row());
+            }
+            self.move_to(0, self.y);
+        }
+    }
+
+    /// Inserts blank cells at the cursor, pushing the rest of the row right
+    fn insert_chars(&mut self, n: u16) {
+        let blank = self.blank();
+        let row = &mut self.grid[self.y as usize];
+        let x = self.x as usize;
+        let n = (n as usize).min(row.len() - x);
+        row[x..].rotate_right(n);
+        row[x..x + n].fill(blank);
+        self.wrap_pending = false;
+    }
+
+    /// Deletes cells at the cursor, pulling the rest of the row left
+    fn delete_chars(&mut self, n: u16) {
+        let blank = self.blank();
+        let row = &mut self.grid[self.y as usize];
+        let x = self.x as usize;
+        let n = (n as usize).min(row.len() - x);
+        row[x..].rotate_left(n);
+        let len = row.len();
+        row[len - n..].fill(blank);
+        self.wrap_pending = false;
+    }
+
+    /// Erases the cells in the row from `xs.start` up to `xs.end`
+    fn erase_in_row(&mut self, y: u16, xs: Range<u16>) {
+        let blank = self.blank();
+        let end = xs.end.min(self.width);
+        let start = xs.start.min(end);
+        self.grid[y as usize][start as usize..end as usize].fill(blank);
+    }
+
+    fn erase_rows(&mut self, ys: Range<u16>) {
+        for y in ys {
+            self.erase_in_row(y, 0..self.width);
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            x: self.x,
+            y: self.y,
+            pen: self.pen,
+            underline_color: self.underline_color,
+        });
+    }
+
+    fn restore_cursor(&mut self) {
+        match self.saved_cursor {
+            Some(saved) => {
+                self.move_to(saved.x, saved.y);
+                self.pen = saved.pen;
+                self.underline_color = saved.underline_color;
+            }
+            None => self.move_to(0, 0),
+        }
+    }
+
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            7 => self.autowrap = enabled,
+            25 => self.cursor_visible = enabled,
+            1004 => self.focus_events = enabled,
+            1049 if enabled != self.alternate_screen => {
+                if enabled {
+                    self.save_cursor();
+                }
+                std::mem::swap(&mut self.grid, &mut self.other_grid);
+                self.alternate_screen = enabled;
+                match enabled {
+                    true => self.erase_rows(0..self.height),
+                    false => self.restore_cursor(),
+                }
+            }
+            2004 => self.bracketed_paste = enabled,
+            2026 => self.synchronized_output = enabled,
+            _ => (),
+        }
+    }
+
+    fn set_underline(&mut self, shape: Option<UnderlineShape>) {
+        self.pen.underline =
+            shape.map(|style| UnderlineStyle { color: self.underline_color, style });
+    }
+
+    fn set_underline_color(&mut self, color: Option<Color>) {
+        self.underline_color = color;
+        if let Some(underline) = &mut self.pen.underline {
+            underline.color = color;
+        }
+    }
+
+    /// Applies the SGR parameters to the pen
+    fn select_graphic_rendition(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.pen = Style::default();
+            self.underline_color = None;
+            return;
+        }
+
+        let mut i = 0;
+        while let Some(param) = params.get(i) {
+            i += 1;
+            match param[0] {
+                0 => {
+                    self.pen = Style::default();
+                    self.underline_color = None;
+                }
+                1 => self.pen.bold = true,
+                2 => self.pen.faint = true,
+                3 => self.pen.italic = true,
+                4 => {
+                    let shape = match param.get(1).copied().unwrap_or(1) {
+                        0 => None,
+                        2 => Some(UnderlineShape::Double),
+                        3 => Some(UnderlineShape::Curly),
+                        4 => Some(UnderlineShape::Dotted),
+                        5 => Some(UnderlineShape::Dashed),
+                        _ => Some(UnderlineShape::Straight),
+                    };
+                    self.set_underline(shape);
+                }
+                7 => self.pen.inverse = true,
+                9 => self.pen.strikethrough = true,
+                21 => self.set_underline(Some(UnderlineShape::Double)),
+                22 => {
+                    self.pen.bold = false;
+                    self.pen.faint = false;
+                }
+                23 => self.pen.italic = false,
+                24 => self.set_underline(None),
+                27 => self.pen.inverse = false,
+                29 => self.pen.strikethrough = false,
+                n @ 30..=37 => self.pen.foreground = Some(Color::Fixed(n as u8 - 30)),
+                n @ 40..=47 => self.pen.background = Some(Color::Fixed(n as u8 - 40)),
+                n @ 90..=97 => self.pen.foreground = Some(Color::Fixed(n as u8 - 90 + 8)),
+                n @ 100..=107 => self.pen.background = Some(Color::Fixed(n as u8 - 100 + 8)),
+                39 => self.pen.foreground = None,
+                49 => self.pen.background = None,
+                59 => self.set_underline_color(None),
+                layer @ (38 | 48 | 58) => {
+                    let color = match param.len() {
+                        // Colon-separated, as in `38:5:<N>` or `38:2:<CS>:<R>:<G>:<B>`
+                        2.. => extended_color(&param[1..]),
+                        // Semicolon-separated, as in `38;5;<N>` or `38;2;<R>;<G>;<B>`
+                        _ => {
+                            let rest: Vec<u16> = (i..params.len())
+                                .map_while(|j| params.get(j).map(|p| p[0]))
+                                .take(4)
+                                .collect();
+                            let color = extended_color_semicolon(&rest);
+                            i += match rest.first() {
+                                Some(5) => 2,
+                                Some(2) => 4,
+                                _ => 0,
+                            };
+                            color
+                        }
+                    };
+
+                    match layer {
+                        38 => self.pen.foreground = color.or(self.pen.foreground),
+                        48 => self.pen.background = color.or(self.pen.background),
+                        _ => self.set_underline_color(color.or(self.underline_color)),
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Parses an extended color from colon-separated sub-parameters, after the initial `38`, `48`, or
+/// `58`
+fn extended_color(sub: &[u16]) -> Option<Color> {
+    let byte = |n: &u16| u8::try_from(*n).ok();
+    match sub {
+        [5, n] => Some(Color::Fixed(byte(n)?)),
+        // With and without the color space identifier
+        [2, _, r, g, b] | [2, r, g, b] => Some(Color::Rgb(byte(r)?, byte(g)?, byte(b)?)),
+        _ => None,
+    }
+}
+
+/// Parses an extended color from the semicolon-separated parameters following `38`, `48`, or `58`
+fn extended_color_semicolon(rest: &[u16]) -> Option<Color> {
+    match rest {
+        [5, n, ..] => extended_color(&[5, *n]),
+        [2, r, g, b, ..] => extended_color(&[2, *r, *g, *b]),
+        _ => None,
+    }
+}
+
+impl Perform for ScreenState {
+    fn print(&mut self, c: char) {
+        if self.wrap_pending && self.autowrap {
+            self.x = 0;
+            self.linefeed();
+        }
+
+        self.grid[self.y as usize][self.x as usize] = Cell { c, style: self.pen };
+        match self.x + 1 < self.width {
+            true => self.x += 1,
+            false => self.wrap_pending = true,
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' | 0x0b | 0x0c => self.linefeed(),
+            b'\r' => self.move_to(0, self.y),
+            0x08 => self.move_to(self.x.saturating_sub(1), self.y),
+            b'\t' => self.move_to((self.x / 8 + 1).saturating_mul(8), self.y),
+            _ => (),
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: u8) {
+        if ignore {
+            return;
+        }
+
+        let n = param_or(params, 0, 1);
+        let (x, y) = (self.x, self.y);
+        match (intermediates, action) {
+            (b"", b'A') => self.move_to(x, y.saturating_sub(n)),
+            (b"", b'B') => self.move_to(x, y.saturating_add(n)),
+            (b"", b'C') => self.move_to(x.saturating_add(n), y),
+            (b"", b'D') => self.move_to(x.saturating_sub(n), y),
+            (b"", b'E') => self.move_to(0, y.saturating_add(n)),
+            (b"", b'F') => self.move_to(0, y.saturating_sub(n)),
+            (b"", b'G') => self.move_to(n - 1, y),
+            (b"", b'd') => self.move_to(x, n - 1),
+            (b"", b'H' | b'f') => self.move_to(param_or(params, 1, 1) - 1, n - 1),
+            (b"", b'J') => match param_or(params, 0, 0) {
+                0 => {
+                    self.erase_in_row(y, x..self.width);
+                    self.erase_rows(y + 1..self.height);
+                }
+                1 => {
+                    self.erase_rows(0..y);
+                    self.erase_in_row(y, 0..x + 1);
+                }
+                2 => self.erase_rows(0..self.height),
+                _ => (),
+            },
+            (b"", b'K') => match param_or(params, 0, 0) {
+                0 => self.erase_in_row(y, x..self.width),
+                1 => self.erase_in_row(y, 0..x + 1),
+                2 => self.erase_in_row(y, 0..self.width),
+                _ => (),
+            },
+            (b"", b'X') => self.erase_in_row(y, x..x.saturating_add(n)),
+            (b"", b'L') => self.insert_lines(n),
+            (b"", b'M') => self.delete_lines(n),
+            (b"", b'@') => self.insert_chars(n),
+            (b"", b'P') => self.delete_chars(n),
+            (b"", b'S') => self.scroll_up(n),
+            (b"", b'T') => self.scroll_down(n),
+            (b"", b'r') => {
+                let top = n - 1;
+                let bottom = param_or(params, 1, self.height).min(self.height);
+                if top < bottom {
+                    self.scroll_region = top..bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            (b"", b's') => self.save_cursor(),
+            (b"", b'u') => self.restore_cursor(),
+            (b"", b'm') => self.select_graphic_rendition(params),
+            (b"", b't') => match params.get(0).map(|p| p[0]) {
+                Some(22) => {
+                    let entry = (self.title.clone(), self.icon_name.clone());
+                    self.title_stack.push(entry);
+                }
+                Some(23) => {
+                    if let Some((title, icon_name)) = self.title_stack.pop() {
+                        self.title = title;
+                        self.icon_name = icon_name;
+                    }
+                }
+                _ => (),
+            },
+            (b"?", b'h' | b'l') => {
+                for param in params.iter() {
+                    self.set_private_mode(param[0], action == b'h');
+                }
+            }
+            (b" ", b'q') => {
+                self.cursor_shape = match param_or(params, 0, 0) {
+                    1 => Some((CursorShape::Block, true)),
+                    2 => Some((CursorShape::Block, false)),
+                    3 => Some((CursorShape::Underline, true)),
+                    4 => Some((CursorShape::Underline, false)),
+                    5 => Some((CursorShape::Bar, true)),
+                    6 => Some((CursorShape::Bar, false)),
+                    _ => None,
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        if ignore || !intermediates.is_empty() {
+            return;
+        }
+
+        match byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.linefeed(),
+            b'E' => {
+                self.move_to(0, self.y);
+                self.linefeed();
+            }
+            b'M' => self.reverse_linefeed(),
+            b'c' => {
+                let (width, height) = (self.width, self.height);
+                *self = VirtualScreen::new(width, height).state;
+            }
+            _ => (),
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        let text = || String::from_utf8_lossy(params.get(1).copied().unwrap_or(b"")).into_owned();
+        match params[0] {
+            b"0" => {
+                self.title = text();
+                self.icon_name = text();
+            }
+            b"1" => self.icon_name = text(),
+            b"2" => self.title = text(),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_virtual_screen() {
+    use crate::{Command, CursorCommand, EraseCommand, ScrollCommand};
+
+    let caps = crate::capinfo::test_caps("kitty");
+    let mut screen = VirtualScreen::new(10, 4);
+    let run = |screen: &mut VirtualScreen, cmd: Command| cmd.write_to(screen, &caps).unwrap();
+
+    let red_bold = Style::new().foreground(Some(Color::Fixed(1))).bold(true);
+    run(&mut screen, Command::SetStyle(red_bold));
+    screen.feed(b"hello\r\nworld!");
+    run(&mut screen, Command::SetStyle(Style::new()));
+    run(
+        &mut screen,
+        Command::Cursor(CursorCommand::MoveTo { x: Some(3), y: Some(2) }),
+    );
+    screen.feed(b"abcdefghij");
+
+    assert_eq!(screen.text(), "hello\nworld!\n   abcdefg\nhij");
+    assert_eq!(screen.cell(1, 0), Some(&Cell { c: 'e', style: red_bold }));
+    assert_eq!(
+        screen.cell(4, 2),
+        Some(&Cell { c: 'b', style: Style::new() })
+    );
+    assert_eq!(screen.cursor(), (3, 3));
+
+    run(
+        &mut screen,
+        Command::Scroll(ScrollCommand::Bounded { y_range: 1..3, amount: 1 }),
+    );
+    assert_eq!(screen.text(), "hello\n   abcdefg\n\nhij");
+
+    run(
+        &mut screen,
+        Command::Cursor(CursorCommand::MoveTo { x: Some(2), y: Some(0) }),
+    );
+    run(&mut screen, Command::Erase(EraseCommand::ToLineEnd));
+    assert_eq!(screen.row_text(0), "he");
+
+    let rgb = Style::new()
+        .background(Some(Color::Rgb(1, 2, 3)))
+        .underline(Some(UnderlineStyle {
+            color: Some(Color::Fixed(200)),
+            style: UnderlineShape::Curly,
+        }));
+    run(&mut screen, Command::SetStyle(rgb));
+    screen.feed(b"x");
+    assert_eq!(screen.cell(2, 0).unwrap().style, rgb);
+
+    run(
+        &mut screen,
+        Command::SetMode { mode: Mode::AlternateScreen, enabled: true },
+    );
+    assert_eq!(screen.text(), "");
+    run(
+        &mut screen,
+        Command::SetMode { mode: Mode::AlternateScreen, enabled: false },
+    );
+    assert_eq!(screen.row_text(0), "hex");
+
+    // Inserting and deleting
+    let mut screen = VirtualScreen::new(5, 3);
+    screen.feed(b"abcde\r\nfghij\r\nklmno\x1b[2;3H");
+    screen.feed(b"\x1b[2@");
+    assert_eq!(screen.row_text(1), "fg  h");
+    screen.feed(b"\x1b[3P");
+    assert_eq!(screen.row_text(1), "fg");
+    screen.feed(b"\x1b[L");
+    assert_eq!(screen.text(), "abcde\n\nfg");
+    assert_eq!(screen.cursor(), (0, 1));
+    screen.feed(b"\x1b[H\x1b[2M");
+    assert_eq!(screen.text(), "fg");
+
+    let mut screen = VirtualScreen::new(0, 0);
+    screen.feed(b"ab\tc");
+    assert_eq!(screen.text(), "c");
+
+    // Tabs stop at the last column, even at the largest width
+    let mut screen = VirtualScreen::new(u16::MAX, 1);
+    screen.feed(b"\x1b[65534G\t");
+    assert_eq!(screen.cursor(), (u16::MAX - 1, 0));
+}